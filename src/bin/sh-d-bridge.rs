@@ -0,0 +1,204 @@
+// Serves the simulated SH-D handle on a pseudo-terminal so the unmodified
+// utility can talk to it like to real hardware.
+//
+//   sh-d-bridge [--link /tmp/ttyAGRG0] [--script events.txt]
+//   AGRG_EXTRA_PORTS=/tmp/ttyAGRG0 agrg-sh-d-util
+//
+// Script commands are read from the script file and then from stdin, one per line.
+
+#[cfg(unix)]
+fn main() {
+    if let Err(e) = bridge::run() {
+        eprintln!("sh-d-bridge: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("sh-d-bridge: pseudo-terminals are only available on unix");
+    std::process::exit(1);
+}
+
+#[cfg(unix)]
+mod bridge {
+    use std::{
+        error::Error,
+        io::{self, BufRead, Read, Write},
+        sync::mpsc,
+        time::Duration,
+    };
+
+    use agrg_sh_d_util::utils::{protocol, sim::Simulator};
+    use chrono::TimeDelta;
+    use serialport::{SerialPort, TTYPort};
+
+    const DEFAULT_LINK: &str = "/tmp/ttyAGRG0";
+
+    const HELP: &str = "\
+commands:
+  power | open | lock | tamper | prog | badpin   log a plain event
+  access <slot>                                  user access by card slot
+  uid <hex>                                      unregistered UID presented
+  pin <digits>                                   unregistered PIN entered
+  event <code> [user] [hex data]                 raw journal entry
+  flood <count>                                  log <count> power-on events
+  drift <seconds>                                shift the device clock
+  text <string>                                  set the custom description
+  sleep <ms>                                     pause the script
+  help";
+
+    pub fn run() -> Result<(), Box<dyn Error>> {
+        let mut link = DEFAULT_LINK.to_string();
+        let mut script = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--link" => link = args.next().ok_or("--link needs a path")?,
+                "--script" => script = Some(args.next().ok_or("--script needs a file")?),
+                _ => return Err(format!("unknown argument: {}", arg).into()),
+            }
+        }
+
+        let (mut master, slave) = TTYPort::pair()?;
+        master.set_timeout(Duration::from_millis(20))?;
+        let slave_name = slave.name().ok_or("pty has no name")?;
+
+        // stale link from a previous run
+        _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(&slave_name, &link)?;
+
+        println!("serving SH-D simulator on {} -> {}", link, slave_name);
+        println!("run the utility with {}={}", agrg_sh_d_util::utils::EXTRA_PORTS_ENV, link);
+
+        let (tx, rx) = mpsc::channel();
+        spawn_reader(script, tx)?;
+
+        let mut sim = Simulator::demo();
+        let mut rx_buf: Vec<u8> = Vec::new();
+        let mut serial_buf = [0u8; 256];
+
+        loop {
+            for line in rx.try_iter() {
+                match script_command(&mut sim, &line) {
+                    Ok(()) => {},
+                    Err(e) => eprintln!("{}: {}", line, e),
+                }
+            }
+
+            match master.read(&mut serial_buf) {
+                Ok(n) => rx_buf.extend_from_slice(&serial_buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {},
+                // nobody holds the other end yet
+                Err(_) => std::thread::sleep(Duration::from_millis(20)),
+            }
+
+            while let Some(len) = protocol::frame_len(&rx_buf) {
+                if rx_buf.len() < len {
+                    break;
+                }
+                let frame: Vec<u8> = rx_buf.drain(..len).collect();
+                let reply = sim.handle(&frame);
+                println!("{:02X?} -> {} bytes", &frame[..4], reply.len());
+                master.write_all(&reply)?;
+                master.flush()?;
+            }
+        }
+    }
+
+    // script file first, then interactive stdin
+    fn spawn_reader(script: Option<String>, tx: mpsc::Sender<String>) -> Result<(), Box<dyn Error>> {
+        let script_lines = match script {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => String::new(),
+        };
+
+        std::thread::spawn(move || {
+            let lines = script_lines.lines()
+                .map(str::to_string)
+                .chain(io::stdin().lock().lines().map_while(Result::ok));
+
+            for line in lines {
+                let line = line.trim().to_string();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                // sleep blocks the reader so later lines wait too
+                if let Some(ms) = line.strip_prefix("sleep ") {
+                    match ms.trim().parse() {
+                        Ok(ms) => std::thread::sleep(Duration::from_millis(ms)),
+                        Err(_) => eprintln!("{}: invalid duration", line),
+                    }
+                    continue;
+                }
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    fn script_command(sim: &mut Simulator, line: &str) -> Result<(), Box<dyn Error>> {
+        let mut parts = line.split_whitespace();
+        let command = parts.next().unwrap_or_default();
+        let args: Vec<&str> = parts.collect();
+
+        match (command, args.as_slice()) {
+            ("power", []) => sim.push_event(0x00, 0x00, [0xFF; 7]),
+            ("open", []) => sim.push_event(0x06, 0x00, [0xFF; 7]),
+            ("tamper", []) => sim.push_event(0x07, 0x00, [0xFF; 7]),
+            ("lock", []) => sim.push_event(0x08, 0x00, [0xFF; 7]),
+            ("prog", []) => sim.push_event(0x09, 0x00, [0xFF; 7]),
+            ("badpin", []) => sim.push_event(0x0A, 0x00, [0xFF; 7]),
+            ("access", [slot]) => sim.push_event(0x01, slot.parse()?, [0xFF; 7]),
+            ("uid", [uid]) => {
+                // first UID byte goes into the user field
+                let bytes = hex::decode(uid)?;
+                let (user, rest) = bytes.split_first().ok_or("empty UID")?;
+                sim.push_event(0x02, *user, padded(rest)?);
+            },
+            ("pin", [pin]) => {
+                let digits = pin.chars()
+                    .map(|c| c.to_digit(10).map(|d| d as u8).ok_or("PIN must be digits"))
+                    .collect::<Result<Vec<u8>, _>>()?;
+                let (user, rest) = digits.split_first().ok_or("empty PIN")?;
+                sim.push_event(0x03, *user, padded(rest)?);
+            },
+            ("event", [code, rest @ ..]) => {
+                let code = u8::from_str_radix(code.trim_start_matches("0x"), 16)?;
+                let user = match rest.first() {
+                    Some(user) => user.parse()?,
+                    None => 0x00,
+                };
+                let data = match rest.get(1) {
+                    Some(data) => padded(&hex::decode(data)?)?,
+                    None => [0xFF; 7],
+                };
+                sim.push_event(code, user, data);
+            },
+            ("flood", [count]) => {
+                for _ in 0..count.parse::<usize>()? {
+                    sim.push_event(0x00, 0x00, [0xFF; 7]);
+                }
+            },
+            ("drift", [seconds]) => sim.drift(TimeDelta::seconds(seconds.parse()?)),
+            ("text", [..]) => sim.set_text(line.trim_start_matches("text").trim()),
+            ("help", []) => println!("{}", HELP),
+            _ => return Err("unknown command, try help".into()),
+        }
+
+        Ok(())
+    }
+
+    fn padded(data: &[u8]) -> Result<[u8; 7], Box<dyn Error>> {
+        if data.len() > 7 {
+            return Err(format!("payload too long: {} bytes (max 7)", data.len()).into());
+        }
+        let mut buffer = [0xFF; 7];
+        buffer[..data.len()].copy_from_slice(data);
+        Ok(buffer)
+    }
+}
@@ -0,0 +1,732 @@
+// Headless access to the handle for provisioning scripts, built on the same
+// utils functions as the GUI.
+//
+//   sh-d-cli [--port NAME | --demo] [--json] [--retries N] [--backoff-ms M] <command> [args]
+//
+// Without --port the first port with a handle answering is used. With --json
+// every command prints one JSON value to stdout, errors included; diagnostics
+// always go to stderr.
+
+use std::{error::Error, fmt, fs, io, path::Path, process::ExitCode, time::Duration};
+
+use agrg_sh_d_util::utils::{
+    self,
+    archive::JournalArchive,
+    backup::Backup,
+    cards::{self, Card, CardRow, TableFormat},
+    container::{self, Container, PayloadKind},
+    error::AgrgError,
+    image::{self, ImportMode, MemoryImage},
+    journal::{self, CsvOptions, Delimiter, ExportEntry, ExportFormat, JournalEntry, ReportInfo},
+    protocol,
+    roster::{self, Names, Roster},
+    sim::Simulator,
+    transfer::{RetryPolicy, Transfer},
+    transport::{SerialTransport, Transport},
+};
+use chrono::Local;
+use serde_json::{json, Value};
+
+const HELP: &str = "\
+usage: sh-d-cli [--port NAME | --demo] [--json] [--retries N] [--backoff-ms M] <command> [args]
+
+commands:
+  ports                               list serial ports
+  info                                device info, description and clock
+  dump --out <file>                   save the device memory as an .agrg image
+  upload <file> [--no-verify]         write settings and cards from an image or raw dump
+  backup --out <file>                 save memory, description, info and clock in one file
+  restore <file> [--no-verify] [--dry-run]
+                                      write settings, cards and description from a backup,
+                                      listing the changes first
+  time get | time sync                read the clock or set it to local time
+  text get | text set <string>        custom description
+  cards list                          enrolled cards
+  cards add <uid> [pin] [--slot N]    enroll into the given or first free slot
+  cards remove <slot>                 clear a slot
+  cards export [--format csv|json] [--out <file>]
+                                      card table as slot,uid,pin,name
+  cards import <file> [--merge]       replace the card table from a .csv or .json file,
+                                      or add its cards to free slots
+  journal export [export options]     journal as CSV, stdout by default
+  journal archive [export options]    add the journal to the local archive, export all of it
+  help
+
+retry options, defaults from AGRG_RETRIES and AGRG_BACKOFF_MS:
+  --retries N                         retries of a failed block, 2 by default
+  --backoff-ms M                      wait before the first retry, doubled on every next one,
+                                      50 by default
+
+export options:
+  --out <file>                        write to a file instead of stdout
+  --format csv|json|html              csv by default, html is a printable report
+  --delimiter comma|semicolon|tab     field separator, comma by default
+  --bom                               start with a UTF-8 BOM for Excel
+
+exit codes:
+  0 ok, 1 device error, 2 bad usage, 3 no handle found, 4 upload not verified,
+  5 file missing or unreadable";
+
+fn main() -> ExitCode {
+    // known before parsing so bad usage is reported as JSON too
+    let json = std::env::args().skip(1).any(|arg| arg == "--json");
+
+    let result = parse_args(std::env::args().skip(1)).and_then(|cli| run(&cli));
+
+    match result {
+        Ok(output) => {
+            match json {
+                true => println!("{}", output.json),
+                false => print!("{}", output.text),
+            }
+            ExitCode::SUCCESS
+        },
+        Err(failure) => {
+            match json {
+                true => println!("{}", json!({ "error": failure.to_string(), "code": failure.code() })),
+                false => eprintln!("sh-d-cli: {}", failure),
+            }
+            ExitCode::from(failure.code())
+        },
+    }
+}
+
+/// Why a command failed, mapped to the exit code.
+#[derive(Debug)]
+enum Failure {
+    Usage(String),
+    NoHandle,
+    Device(Box<dyn Error>),
+    /// Upload blocks that still differ after rewriting.
+    NotVerified(Vec<u16>),
+    /// Input file that could not be read or parsed.
+    File(String),
+}
+
+impl Failure {
+    fn code(&self) -> u8 {
+        match self {
+            Failure::Device(_) => 1,
+            Failure::Usage(_) => 2,
+            Failure::NoHandle => 3,
+            Failure::NotVerified(_) => 4,
+            Failure::File(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Usage(msg) => write!(f, "{}, try help", msg),
+            Failure::NoHandle => write!(f, "handle not found"),
+            Failure::Device(e) => write!(f, "{}", e),
+            Failure::NotVerified(addrs) => write!(f, "upload not verified, mismatched blocks: {:04X?}", addrs),
+            Failure::File(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<Box<dyn Error>> for Failure {
+    fn from(e: Box<dyn Error>) -> Self {
+        Failure::Device(e)
+    }
+}
+
+impl From<AgrgError> for Failure {
+    fn from(e: AgrgError) -> Self {
+        Failure::Device(e.into())
+    }
+}
+
+impl From<&str> for Failure {
+    fn from(e: &str) -> Self {
+        Failure::Device(e.into())
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Device(e.into())
+    }
+}
+
+fn usage(msg: impl Into<String>) -> Failure {
+    Failure::Usage(msg.into())
+}
+
+// reads and parses an input file before any port is opened
+fn read_file<T>(path: &str, parse: impl FnOnce(Vec<u8>) -> Result<T, AgrgError>) -> Result<T, Failure> {
+    fs::read(path)
+        .map_err(AgrgError::from)
+        .and_then(parse)
+        .map_err(|e| Failure::File(format!("{}: {}", path, e)))
+}
+
+struct Cli {
+    port: Option<String>,
+    demo: bool,
+    json: bool,
+    retry: RetryPolicy,
+    args: Vec<String>,
+}
+
+/// What a command prints, as text and as JSON.
+struct Output {
+    text: String,
+    json: Value,
+}
+
+impl Output {
+    fn new(text: impl Into<String>, json: Value) -> Self {
+        Self { text: text.into(), json }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Cli, Failure> {
+    let mut cli = Cli { port: None, demo: false, json: false, retry: RetryPolicy::from_env(), args: Vec::new() };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => cli.port = Some(args.next().ok_or_else(|| usage("--port needs a name"))?),
+            "--demo" => cli.demo = true,
+            "--json" => cli.json = true,
+            "--retries" => cli.retry.set_retries(number_arg(&mut args, "--retries")?),
+            "--backoff-ms" => cli.retry.backoff = Duration::from_millis(number_arg(&mut args, "--backoff-ms")?),
+            _ => cli.args.push(arg),
+        }
+    }
+
+    Ok(cli)
+}
+
+fn number_arg<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, Failure> {
+    let val = args.next().ok_or_else(|| usage(format!("{} needs a number", flag)))?;
+    val.parse().map_err(|_| usage(format!("{}: not a number: {}", flag, val)))
+}
+
+/// A parsed command line, checked before any port is opened.
+enum Cmd {
+    Help,
+    Ports,
+    Info,
+    Dump { out: String },
+    Upload { path: String, image: MemoryImage, verify: bool },
+    Backup { out: String },
+    // read before the port is opened
+    Restore { path: String, backup: Box<Backup>, verify: bool, dry_run: bool },
+    TimeGet,
+    TimeSync,
+    TextGet,
+    TextSet(String),
+    CardsList,
+    CardsAdd { card: Card, slot: Option<usize> },
+    CardsRemove(usize),
+    CardsExport { format: TableFormat, out: Option<String> },
+    // validated before the port is opened
+    CardsImport { rows: Vec<CardRow>, mode: ImportMode },
+    JournalExport(JournalOut),
+    JournalArchive(JournalOut),
+}
+
+// where and how a journal command writes its export
+struct JournalOut {
+    path: Option<String>,
+    format: ExportFormat,
+    csv: CsvOptions,
+}
+
+fn parse_command(args: &[&str]) -> Result<Cmd, Failure> {
+    let cmd = match args {
+        [] | ["help"] => Cmd::Help,
+        ["ports"] => Cmd::Ports,
+        ["info"] => Cmd::Info,
+        ["dump", "--out", out] => Cmd::Dump { out: out.to_string() },
+        ["upload", path] | ["upload", path, "--no-verify"] | ["upload", "--no-verify", path] => Cmd::Upload {
+            path: path.to_string(),
+            // plain dumps from older versions are still accepted
+            image: read_file(path, |data| MemoryImage::from_dump(container::open(data, PayloadKind::Image)?))?,
+            verify: !args.contains(&"--no-verify"),
+        },
+        ["backup", "--out", out] => Cmd::Backup { out: out.to_string() },
+        ["restore", path, flags @ ..] => {
+            if let Some(flag) = flags.iter().find(|flag| !["--no-verify", "--dry-run"].contains(flag)) {
+                return Err(usage(format!("unknown restore option: {}", flag)));
+            }
+            let backup = read_file(path, |data| Backup::parse(&data))?;
+            Cmd::Restore {
+                path: path.to_string(),
+                backup: Box::new(backup),
+                verify: !flags.contains(&"--no-verify"),
+                dry_run: flags.contains(&"--dry-run"),
+            }
+        },
+        ["time", "get"] => Cmd::TimeGet,
+        ["time", "sync"] => Cmd::TimeSync,
+        ["text", "get"] => Cmd::TextGet,
+        ["text", "set", words @ ..] => {
+            let text = words.join(" ");
+            if text.len() > protocol::TEXT_SIZE {
+                return Err(usage(format!("text too long: {} bytes (max {})", text.len(), protocol::TEXT_SIZE)));
+            }
+            Cmd::TextSet(text)
+        },
+        ["cards", "list"] => Cmd::CardsList,
+        ["cards", "add", rest @ ..] => {
+            let (uid, pin, slot) = match rest {
+                [uid] => (uid, "", None),
+                [uid, pin] => (uid, *pin, None),
+                [uid, "--slot", slot] => (uid, "", Some(parse_slot(slot)?)),
+                [uid, pin, "--slot", slot] => (uid, *pin, Some(parse_slot(slot)?)),
+                _ => return Err(usage("cards add needs <uid> [pin] [--slot N]")),
+            };
+            cards::rfid_to_bytes(uid.to_string()).map_err(|e| usage(e.to_string()))?;
+            cards::pin_to_bytes(pin.to_string()).map_err(|e| usage(e.to_string()))?;
+            Cmd::CardsAdd { card: Card { rfid: uid.to_string(), pin: pin.to_string() }, slot }
+        },
+        ["cards", "remove", slot] => Cmd::CardsRemove(parse_slot(slot)?),
+        ["cards", "export", rest @ ..] => {
+            let (format, out) = match rest {
+                [] => (TableFormat::Csv, None),
+                ["--out", out] => (TableFormat::of(Path::new(out)), Some(out.to_string())),
+                ["--format", format] => (parse_table_format(format)?, None),
+                ["--format", format, "--out", out] | ["--out", out, "--format", format] =>
+                    (parse_table_format(format)?, Some(out.to_string())),
+                _ => return Err(usage("cards export takes [--format csv|json] [--out <file>]")),
+            };
+            Cmd::CardsExport { format, out }
+        },
+        ["cards", "import", path] | ["cards", "import", path, "--merge"] | ["cards", "import", "--merge", path] => {
+            let mode = match args.contains(&"--merge") {
+                true => ImportMode::Merge,
+                false => ImportMode::Replace,
+            };
+            let rows = read_file(path, |data| cards::read_table(&data, TableFormat::of(Path::new(path))))?;
+            Cmd::CardsImport { rows, mode }
+        },
+        ["journal", "export", rest @ ..] => Cmd::JournalExport(parse_journal_out(rest)?),
+        ["journal", "archive", rest @ ..] => Cmd::JournalArchive(parse_journal_out(rest)?),
+        _ => return Err(usage(format!("unknown command: {}", args.join(" ")))),
+    };
+    Ok(cmd)
+}
+
+fn parse_journal_out(mut args: &[&str]) -> Result<JournalOut, Failure> {
+    let mut out = JournalOut { path: None, format: ExportFormat::default(), csv: CsvOptions::default() };
+
+    loop {
+        args = match args {
+            [] => return Ok(out),
+            ["--out", path, rest @ ..] => {
+                out.path = Some(path.to_string());
+                rest
+            },
+            ["--format", format, rest @ ..] => {
+                out.format = ExportFormat::ALL.into_iter()
+                    .find(|f| f.extension() == *format)
+                    .ok_or_else(|| usage(format!("unknown format: {}", format)))?;
+                rest
+            },
+            ["--delimiter", delimiter, rest @ ..] => {
+                out.csv.delimiter = match *delimiter {
+                    "comma" => Delimiter::Comma,
+                    "semicolon" => Delimiter::Semicolon,
+                    "tab" => Delimiter::Tab,
+                    other => return Err(usage(format!("unknown delimiter: {}", other))),
+                };
+                rest
+            },
+            ["--bom", rest @ ..] => {
+                out.csv.bom = true;
+                rest
+            },
+            [other, ..] => return Err(usage(format!("unknown journal option: {}", other))),
+        };
+    }
+}
+
+fn run(cli: &Cli) -> Result<Output, Failure> {
+    let args: Vec<&str> = cli.args.iter().map(String::as_str).collect();
+    let cmd = parse_command(&args)?;
+
+    // commands that need no handle
+    match cmd {
+        Cmd::Help => return Ok(Output::new(format!("{}\n", HELP), json!({ "help": HELP }))),
+        Cmd::Ports => return Ok(ports()),
+        _ => {},
+    }
+
+    let mut transport = connect(cli)?;
+    let transport = transport.as_mut();
+    let policy = cli.retry;
+
+    match cmd {
+        Cmd::Help | Cmd::Ports => unreachable!(),
+        Cmd::Info => info(transport),
+        Cmd::Dump { out } => dump(transport, &policy, &out),
+        Cmd::Upload { path, image, verify } => upload(transport, &policy, &path, &image, verify),
+        Cmd::Backup { out } => backup(transport, &policy, &out),
+        Cmd::Restore { path, backup, verify, dry_run } => restore(transport, &policy, &path, &backup, verify, dry_run),
+        Cmd::TimeGet => time_get(transport),
+        Cmd::TimeSync => time_sync(transport),
+        Cmd::TextGet => {
+            let text = utils::read_text(transport)?;
+            Ok(Output::new(format!("{}\n", text), json!({ "text": text })))
+        },
+        Cmd::TextSet(text) => {
+            utils::write_text(transport, &text)?;
+            Ok(Output::new(format!("{}\n", text), json!({ "text": text })))
+        },
+        Cmd::CardsList => cards_list(transport, &policy),
+        Cmd::CardsAdd { card, slot } => cards_add(transport, &policy, card, slot),
+        Cmd::CardsRemove(slot) => cards_remove(transport, &policy, slot),
+        Cmd::CardsExport { format, out } => cards_export(transport, &policy, format, out.as_deref()),
+        Cmd::CardsImport { rows, mode } => cards_import(transport, &policy, &rows, mode),
+        Cmd::JournalExport(out) => journal_export(transport, &policy, &out),
+        Cmd::JournalArchive(out) => journal_archive(transport, &policy, &out),
+    }
+}
+
+fn connect(cli: &Cli) -> Result<Box<dyn Transport>, Failure> {
+    if cli.demo {
+        return Ok(Box::new(Simulator::demo()));
+    }
+
+    match &cli.port {
+        Some(port) => utils::check_handle(port)
+            .map(|t| Box::new(t) as Box<dyn Transport>)
+            .ok_or(Failure::NoHandle),
+        None => utils::get_available_ports()
+            .unwrap_or_default()
+            .iter()
+            .find_map(|port| utils::check_handle(port))
+            .map(|t: SerialTransport| {
+                eprintln!("found handle on port {}", t.name());
+                Box::new(t) as Box<dyn Transport>
+            })
+            .ok_or(Failure::NoHandle),
+    }
+}
+
+fn ports() -> Output {
+    let ports = utils::get_available_ports().unwrap_or_default();
+    let text: String = ports.iter().map(|p| format!("{}\n", p)).collect();
+    Output::new(text, json!(ports))
+}
+
+fn info(transport: &mut dyn Transport) -> Result<Output, Failure> {
+    let info = utils::agrg_text_info(transport).ok_or("no reply to info request")?;
+    let text = utils::read_text(transport)?;
+    let time = device_time(transport)?;
+
+    Ok(Output::new(
+        format!("{}\ntext: {}\ntime: {}\n", info.trim_end(), text, time.as_deref().unwrap_or("not set")),
+        json!({ "info": info.trim_end(), "text": text, "time": time }),
+    ))
+}
+
+fn dump(transport: &mut dyn Transport, policy: &RetryPolicy, path: &str) -> Result<Output, Failure> {
+    let mut dump = Transfer::dump();
+    utils::mem_dump(transport, policy, &mut dump, &mut |_| true)?;
+    eprint!("{}", dump.report());
+
+    let len = dump.data.len();
+    let device = utils::agrg_text_info(transport);
+    fs::write(path, Container::new(PayloadKind::Image, device, dump.data.clone()).to_bytes())?;
+
+    Ok(Output::new(
+        format!("{} bytes written to {}\n", len, path),
+        json!({ "path": path, "bytes": len, "retried": retried(&dump) }),
+    ))
+}
+
+fn upload(transport: &mut dyn Transport, policy: &RetryPolicy, path: &str, image: &MemoryImage, verify: bool) -> Result<Output, Failure> {
+    let mut upload = Transfer::upload(image.upload_region().to_vec());
+    utils::mem_upload(transport, policy, &mut upload, &mut |_| true)?;
+    eprint!("{}", upload.report());
+
+    if verify {
+        verify_upload(transport, policy, &upload.data)?;
+    }
+
+    Ok(Output::new(
+        format!("{} uploaded{}\n", path, if verify { ", verified" } else { "" }),
+        json!({ "path": path, "verified": verify, "retried": retried(&upload) }),
+    ))
+}
+
+fn backup(transport: &mut dyn Transport, policy: &RetryPolicy, path: &str) -> Result<Output, Failure> {
+    let mut dump = Transfer::dump();
+    let backup = Backup::read(transport, policy, &mut dump, &mut |_| true)?;
+    eprint!("{}", dump.report());
+
+    fs::write(path, backup.to_bytes())?;
+
+    let time = backup.time().map(|time| time.format("%H:%M:%S %d.%m.%Y").to_string());
+    Ok(Output::new(
+        format!("backup written to {}, clock {}\n", path, time.as_deref().unwrap_or("not set")),
+        json!({ "path": path, "text": backup.text, "time": time, "retried": retried(&dump) }),
+    ))
+}
+
+fn restore(
+    transport: &mut dyn Transport,
+    policy: &RetryPolicy,
+    path: &str,
+    backup: &Backup,
+    verify: bool,
+    dry_run: bool,
+) -> Result<Output, Failure> {
+    let current = read_cards(transport, policy)?;
+    let text = utils::read_text(transport)?;
+    let changes = backup.changes(Some(current.upload_region()), &text);
+
+    let device = utils::agrg_text_info(transport);
+    if backup.device != device {
+        eprintln!("backup was taken from another handle: {}", backup.device.as_deref().unwrap_or("unknown").trim_end());
+    }
+    let mut listed: String = changes.iter().map(|change| format!("{}\n", change)).collect();
+    if changes.is_empty() {
+        listed.push_str("nothing to restore\n");
+    }
+
+    if dry_run || changes.is_empty() {
+        return Ok(Output::new(listed, json!({ "path": path, "changes": changes, "restored": false })));
+    }
+
+    let mut upload = Transfer::upload_diff(backup.image.upload_region().to_vec(), Some(current.upload_region().to_vec()));
+    utils::mem_upload(transport, policy, &mut upload, &mut |_| true)?;
+    eprint!("{}", upload.report());
+
+    if verify {
+        verify_upload(transport, policy, &upload.data)?;
+    }
+    if backup.text != text {
+        utils::write_text(transport, &backup.text)?;
+    }
+
+    listed.push_str(&format!("{} restored{}\n", path, if verify { ", verified" } else { "" }));
+    Ok(Output::new(
+        listed,
+        json!({ "path": path, "changes": changes, "restored": true, "verified": verify, "retried": retried(&upload) }),
+    ))
+}
+
+// rewrites mismatched blocks once before giving up
+fn verify_upload(transport: &mut dyn Transport, policy: &RetryPolicy, data: &[u8]) -> Result<(), Failure> {
+    let mut mismatched = utils::verify_upload(transport, policy, data)?;
+
+    if !mismatched.is_empty() {
+        utils::rewrite_blocks(transport, policy, data, &mismatched)?;
+        mismatched = utils::verify_upload(transport, policy, data)?;
+    }
+
+    match mismatched.is_empty() {
+        true => Ok(()),
+        false => Err(Failure::NotVerified(mismatched)),
+    }
+}
+
+fn retried(transfer: &Transfer) -> Value {
+    transfer.retried.iter()
+        .map(|b| json!({ "addr": b.addr, "attempts": b.attempts }))
+        .collect()
+}
+
+// None for a reset clock, the handle answers with whatever the RTC holds
+fn device_time(transport: &mut dyn Transport) -> Result<Option<String>, Failure> {
+    let raw: [u8; protocol::CLOCK_SIZE] = utils::get_datetime(transport)?
+        .try_into()
+        .map_err(|_| "Invalid datetime length")?;
+
+    Ok(protocol::bcd_to_datetime(&raw).map(|time| time.format("%H:%M:%S %d.%m.%Y").to_string()))
+}
+
+fn time_get(transport: &mut dyn Transport) -> Result<Output, Failure> {
+    let time = device_time(transport)?.ok_or("device clock is not set, run time sync")?;
+    Ok(Output::new(format!("{}\n", time), json!({ "time": time })))
+}
+
+fn time_sync(transport: &mut dyn Transport) -> Result<Output, Failure> {
+    let time = Local::now().format("%H:%M:%S %d.%m.%Y").to_string();
+    utils::set_datetime(transport, time.clone())?;
+    Ok(Output::new(format!("{}\n", time), json!({ "time": time })))
+}
+
+// settings and cards only, the journal is not needed for card edits
+fn read_cards(transport: &mut dyn Transport, policy: &RetryPolicy) -> Result<MemoryImage, Failure> {
+    let mut dump = Transfer::dump();
+    utils::mem_read(transport, policy, &mut dump, image::CARDS.end)?;
+    Ok(MemoryImage::from_dump(dump.data)?)
+}
+
+fn enrolled(image: &MemoryImage) -> Vec<(usize, Card)> {
+    image.cards()
+        .filter_map(|(slot, card)| card.ok().map(|card| (slot, card)))
+        .filter(|(_, card)| !card.rfid.is_empty() || !card.pin.is_empty())
+        .collect()
+}
+
+fn cards_list(transport: &mut dyn Transport, policy: &RetryPolicy) -> Result<Output, Failure> {
+    let cards = enrolled(&read_cards(transport, policy)?);
+
+    let text: String = cards.iter()
+        .map(|(slot, card)| format!("{:3} {:20} {}\n", slot, card.rfid, card.pin))
+        .collect();
+    let json: Value = cards.iter()
+        .map(|(slot, card)| json!({ "slot": slot, "uid": card.rfid, "pin": card.pin }))
+        .collect();
+
+    Ok(Output::new(text, json))
+}
+
+fn cards_add(transport: &mut dyn Transport, policy: &RetryPolicy, card: Card, slot: Option<usize>) -> Result<Output, Failure> {
+    let snapshot = read_cards(transport, policy)?;
+    let mut image = snapshot.clone();
+    let slot = match slot {
+        Some(slot) => {
+            image.set_card(slot, &card)?;
+            slot
+        },
+        None => image.enroll(&card)?,
+    };
+    write_changes(transport, policy, &snapshot, &image)?;
+
+    Ok(Output::new(
+        format!("{} {} {}\n", slot, card.rfid, card.pin),
+        json!({ "slot": slot, "uid": card.rfid, "pin": card.pin }),
+    ))
+}
+
+fn cards_remove(transport: &mut dyn Transport, policy: &RetryPolicy, slot: usize) -> Result<Output, Failure> {
+    let snapshot = read_cards(transport, policy)?;
+
+    let mut image = snapshot.clone();
+    image.set_card(slot, &Card { rfid: String::new(), pin: String::new() })?;
+    write_changes(transport, policy, &snapshot, &image)?;
+
+    Ok(Output::new(format!("{} removed\n", slot), json!({ "slot": slot })))
+}
+
+fn cards_export(transport: &mut dyn Transport, policy: &RetryPolicy, format: TableFormat, path: Option<&str>) -> Result<Output, Failure> {
+    let image = read_cards(transport, policy)?;
+    let roster = utils::agrg_text_info(transport).and_then(|device| Roster::open(&device).ok());
+    let rows = roster::card_table(&image, roster.as_ref());
+
+    let mut file = Vec::new();
+    cards::write_table(&mut file, &rows, format)?;
+
+    match path {
+        Some(path) => {
+            fs::write(path, &file)?;
+            Ok(Output::new(format!("{} cards written to {}\n", rows.len(), path), json!({ "path": path, "cards": rows })))
+        },
+        None => Ok(Output::new(String::from_utf8_lossy(&file), json!(rows))),
+    }
+}
+
+fn cards_import(transport: &mut dyn Transport, policy: &RetryPolicy, rows: &[CardRow], mode: ImportMode) -> Result<Output, Failure> {
+    let snapshot = read_cards(transport, policy)?;
+
+    let mut image = snapshot.clone();
+    let imported = image.import_cards(rows, mode)?;
+    write_changes(transport, policy, &snapshot, &image)?;
+
+    // the cards are on the device, names are best effort
+    if let Some(device) = utils::agrg_text_info(transport) {
+        let saved = Roster::open(&device).and_then(|mut roster| {
+            roster.import(&imported, mode);
+            roster.save()
+        });
+        if let Err(e) = saved {
+            eprintln!("names not saved: {}", e);
+        }
+    }
+
+    for row in &imported.skipped {
+        eprintln!("skipped {}: already enrolled", row.uid);
+    }
+    let placed: Vec<Value> = imported.placed.iter()
+        .map(|(slot, row)| json!({ "slot": slot, "uid": row.uid, "pin": row.pin, "name": row.name }))
+        .collect();
+
+    Ok(Output::new(
+        format!("{} cards imported, {} skipped\n", imported.placed.len(), imported.skipped.len()),
+        json!({ "placed": placed, "skipped": imported.skipped }),
+    ))
+}
+
+// writes only the blocks that differ from what was just read, then verifies them
+fn write_changes(transport: &mut dyn Transport, policy: &RetryPolicy, snapshot: &MemoryImage, image: &MemoryImage) -> Result<(), Failure> {
+    let data = image.upload_region().to_vec();
+    let mut upload = Transfer::upload_diff(data, Some(snapshot.upload_region().to_vec()));
+    utils::mem_upload(transport, policy, &mut upload, &mut |_| true)?;
+    verify_upload(transport, policy, &upload.data)
+}
+
+fn parse_table_format(format: &str) -> Result<TableFormat, Failure> {
+    match format {
+        "csv" => Ok(TableFormat::Csv),
+        "json" => Ok(TableFormat::Json),
+        other => Err(usage(format!("unknown card table format: {}", other))),
+    }
+}
+
+fn parse_slot(slot: &str) -> Result<usize, Failure> {
+    match slot.parse() {
+        Ok(slot @ 1..=image::SLOT_COUNT) => Ok(slot),
+        _ => Err(usage(format!("slot must be 1..={}, got {}", image::SLOT_COUNT, slot))),
+    }
+}
+
+fn journal_export(transport: &mut dyn Transport, policy: &RetryPolicy, out: &JournalOut) -> Result<Output, Failure> {
+    let (image, entries) = read_journal(transport, policy)?;
+    let device = utils::agrg_text_info(transport);
+    let info = ReportInfo::now(device.clone(), utils::read_text(transport).ok());
+
+    // names are a nicety, the export works without a roster
+    let roster = device.and_then(|device| Roster::open(&device).ok());
+    write_journal(&entries, &Names::new(roster.as_ref(), &image), &info, out)
+}
+
+fn journal_archive(transport: &mut dyn Transport, policy: &RetryPolicy, out: &JournalOut) -> Result<Output, Failure> {
+    let device = utils::agrg_text_info(transport).ok_or("device info unavailable")?;
+    let mut archive = JournalArchive::open(&device)?;
+    let roster = Roster::open(&device)?;
+    let info = ReportInfo::now(Some(device), utils::read_text(transport).ok());
+
+    let (image, entries) = read_journal(transport, policy)?;
+    let added = archive.merge(&entries)?;
+    eprintln!("{}: {} new entries", archive.path().display(), added);
+
+    write_journal(archive.entries(), &Names::new(Some(&roster), &image), &info, out)
+}
+
+// the whole image is kept to name the slots the journal refers to
+fn read_journal(transport: &mut dyn Transport, policy: &RetryPolicy) -> Result<(MemoryImage, Vec<JournalEntry>), Failure> {
+    let mut dump = Transfer::dump();
+    utils::mem_dump(transport, policy, &mut dump, &mut |_| true)?;
+    let image = MemoryImage::from_dump(dump.data)?;
+
+    let journal = journal::order(image.journal());
+    for issue in &journal.issues {
+        eprintln!("journal: {}", issue);
+    }
+    Ok((image, journal.entries))
+}
+
+fn write_journal(entries: &[JournalEntry], names: &Names, info: &ReportInfo, out: &JournalOut) -> Result<Output, Failure> {
+    let json: Vec<ExportEntry> = entries.iter().map(|entry| ExportEntry::new(entry, names)).collect();
+
+    let mut file = Vec::new();
+    journal::write_export(&mut file, entries, names, out.format, out.csv, info)?;
+
+    match &out.path {
+        Some(path) => {
+            fs::write(path, &file)?;
+            Ok(Output::new(format!("journal written to {}\n", path), json!({ "path": path, "entries": json })))
+        },
+        None => Ok(Output::new(String::from_utf8_lossy(&file), json!(json))),
+    }
+}
+
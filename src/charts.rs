@@ -0,0 +1,78 @@
+use iced::{
+    alignment::{Horizontal, Vertical},
+    mouse,
+    widget::canvas::{self, Frame, Geometry, Text},
+    Color, Point, Rectangle, Renderer, Size, Theme,
+};
+
+// room for the value above a bar and the label below it
+const VALUE_HEIGHT: f32 = 14.0;
+const LABEL_HEIGHT: f32 = 18.0;
+// narrowest gap between two labels before some get skipped
+const LABEL_WIDTH: f32 = 36.0;
+
+/// Vertical bars scaled to the tallest one, labelled underneath.
+pub struct BarChart {
+    bars: Vec<(String, usize)>,
+}
+
+impl BarChart {
+    pub fn new(bars: Vec<(String, usize)>) -> Self {
+        Self { bars }
+    }
+}
+
+impl<Message> canvas::Program<Message> for BarChart {
+    type State = ();
+
+    fn draw(&self, _state: &(), renderer: &Renderer, theme: &Theme, bounds: Rectangle, _cursor: mouse::Cursor) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        let text = theme.palette().text;
+
+        let max = self.bars.iter().map(|(_, value)| *value).max().unwrap_or_default();
+        if self.bars.is_empty() || max == 0 {
+            return vec![frame.into_geometry()];
+        }
+
+        let slot = frame.width() / self.bars.len() as f32;
+        let plot = frame.height() - VALUE_HEIGHT - LABEL_HEIGHT;
+        let label_every = (LABEL_WIDTH / slot).ceil().max(1.0) as usize;
+
+        for (index, (label, value)) in self.bars.iter().enumerate() {
+            let height = plot * *value as f32 / max as f32;
+            let x = index as f32 * slot;
+            let center = x + slot / 2.0;
+
+            frame.fill_rectangle(
+                Point::new(x + slot * 0.1, VALUE_HEIGHT + plot - height),
+                Size::new(slot * 0.8, height),
+                Color::from_rgb(0.0, 0.73, 0.89),
+            );
+
+            if *value > 0 && slot >= LABEL_WIDTH / 2.0 {
+                frame.fill_text(Text {
+                    content: value.to_string(),
+                    position: Point::new(center, VALUE_HEIGHT + plot - height),
+                    color: text,
+                    size: 11.0.into(),
+                    horizontal_alignment: Horizontal::Center,
+                    vertical_alignment: Vertical::Bottom,
+                    ..Text::default()
+                });
+            }
+            if index % label_every == 0 {
+                frame.fill_text(Text {
+                    content: label.clone(),
+                    position: Point::new(center, frame.height() - LABEL_HEIGHT + 2.0),
+                    color: text,
+                    size: 11.0.into(),
+                    horizontal_alignment: Horizontal::Center,
+                    vertical_alignment: Vertical::Top,
+                    ..Text::default()
+                });
+            }
+        }
+
+        vec![frame.into_geometry()]
+    }
+}
@@ -1,17 +1,37 @@
 //#![cfg_attr(windows, windows_subsystem = "windows")]
 
-mod utils;
 mod styles;
 mod logo;
+mod charts;
 
-use std::time::Duration;
+use std::{
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, MutexGuard, PoisonError, TryLockError},
+    time::Duration,
+};
 
 use base64::{Engine as _, engine::general_purpose};
 
 use iced::{
-    alignment::Horizontal, widget::{button, column, container, pick_list, row, scrollable, text_input, Column, Container, Image, Row, Space, Text, Toggler}, Alignment, Application, Color, Length, Settings
+    alignment::Horizontal, futures::SinkExt, widget::{button, canvas, column, container, pick_list, progress_bar, row, scrollable, text_input, Column, Container, Image, Row, Space, Text, Toggler}, Alignment, Application, Color, Length, Settings
+};
+use chrono::{Local, NaiveDate};
+
+use charts::BarChart;
+
+use agrg_sh_d_util::utils::{
+    self,
+    archive::JournalArchive,
+    backup::{self, Backup},
+    cards::{Card, TableFormat},
+    image::{ImportMode, MemoryImage},
+    journal::{CsvOptions, Delimiter, EventKind, ExportFormat, ReportInfo, JournalEntry, JournalEvent, JournalFilter, FilterCounts, OrderedJournal},
+    roster::{Holder, Names, Roster},
+    stats::JournalStats,
+    settings::{option_from_byte, option_to_byte, AccessMode, DeviceSettings, PinpadFormat, ReaderFormat, WorkMode, FIELD_NAMES},
+    error::AgrgError,
+    transfer::{Progress, RetryPolicy, Transfer},
+    transport::Transport,
 };
-use chrono::Local;
 
 fn main() -> iced::Result {
     Agrg::run(Settings::default())
@@ -21,7 +41,8 @@ fn main() -> iced::Result {
 enum Tab {
     Settings,
     Cards,
-    Journal
+    Journal,
+    Statistics
 }
 
 #[derive(Debug, Clone)]
@@ -30,22 +51,121 @@ enum AgrgMsg {
     SaveCustomData,
     PingKeepAlive,
     ToggleKeepAlive,
-    SettingsUpdate(usize, String),
+    ToggleVerify,
+    ToggleRewrite,
+    // option byte index and its new value
+    SettingChanged(usize, u8),
     AdminPasswdEdited(String),
     SettingsTab,
     JournalTab,
+    StatisticsTab,
     CardsTab,
     SerialChoice(String),
     RefreshPorts,
     MemDump,
     ExportJournal,
+    ExportReport,
     ExportCards,
     ImportCards,
+    ExportCardTable(TableFormat),
+    ImportCardTable(ImportMode),
     ExportSettings,
     ImportSettings,
     MemUpload,
+    ConfirmUpload,
+    CancelUpload,
+    MakeBackup,
+    BackupFinished(Transfer, Result<Box<Backup>, String>),
+    Restore,
     TimeSync,
-    CardEdited(usize, bool, String), // index / UID(0) or PIN(1) / new_value
+    DismissError,
+    TransferProgress(Progress),
+    CancelTransfer,
+    DumpFinished(Transfer, Result<(), String>),
+    // upload state, how the read-back went
+    UploadFinished(Transfer, Result<Verified, String>),
+    CardEdited(usize, bool, String), // slot / UID(0) or PIN(1) / new_value
+    FilterFrom(String),
+    FilterTo(String),
+    FilterKind(KindChoice),
+    FilterSlot(String),
+    FilterUid(String),
+    ResetFilter,
+    ToggleArchive,
+    HolderEdited(usize, Holder),
+    EnrollPinEdited(String),
+    ExportFormatChosen(ExportFormat),
+    CsvDelimiter(Delimiter),
+    ToggleBom,
+    // UID from a journal row, into the first free slot
+    EnrollUid(String),
+}
+
+// outcome of reading the written blocks back
+#[derive(Debug, Clone)]
+enum Verified {
+    Skipped,
+    Passed,
+    // still different after the rewrite, if one was allowed
+    Mismatched(Vec<u16>),
+    Failed(String),
+}
+
+impl Verified {
+    // whether the device now holds the image, unchecked counts as held
+    fn stored(&self) -> bool {
+        matches!(self, Verified::Skipped | Verified::Passed)
+    }
+}
+
+// format picked for the journal export button
+#[derive(Default, Clone, Copy)]
+struct JournalExport {
+    format: ExportFormat,
+    csv: CsvOptions,
+}
+
+// event type pick list entry, `All` clears that filter only
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KindChoice {
+    All,
+    Kind(EventKind),
+}
+
+impl std::fmt::Display for KindChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KindChoice::All => write!(f, "Все"),
+            KindChoice::Kind(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+// journal tab filters, dates and slot as typed and parsed on every edit
+#[derive(Default)]
+struct JournalSearch {
+    from: String,
+    to: String,
+    slot: String,
+    filter: JournalFilter,
+}
+
+// journal as the tabs show it, redone only when the dump, the archive or the filter changes
+struct JournalCache {
+    // dumped journal in ring order
+    ordered: OrderedJournal,
+    // dumped journal or archive with the filter applied
+    shown: Vec<JournalEntry>,
+    counts: FilterCounts,
+    stats: JournalStats,
+}
+
+// the background transfer holds the lock for its whole duration
+type SharedTransport = Arc<Mutex<Box<dyn Transport>>>;
+
+struct Job {
+    progress: Progress,
+    cancel: Arc<AtomicBool>,
 }
 
 struct Agrg {
@@ -53,12 +173,38 @@ struct Agrg {
     tab: Tab,
     ports: Vec<String>,
     port: Option<String>,
-    data: Vec<u8>,
+    data: MemoryImage,
     admin_paswd: String,
     time: String,
-    settings_map: Vec<Vec<String>>,
     logo: iced::widget::image::Handle,
-    connected: bool,
+    transport: Option<SharedTransport>,
+    // dump or upload running in the background
+    job: Option<Job>,
+    // last failure, shown until dismissed
+    error: Option<String>,
+    retry: RetryPolicy,
+    // interrupted transfers, resumed on the next attempt
+    pending_dump: Option<Transfer>,
+    pending_upload: Option<Transfer>,
+    // read back after upload, rewrite blocks that differ
+    verify: bool,
+    rewrite_mismatched: bool,
+    // settings and cards as last read from (or written to) the device
+    snapshot: Option<Vec<u8>>,
+    // changes awaiting confirmation before upload
+    upload_preview: Option<Vec<String>>,
+    // backup being restored, the upload writes its image and description
+    restore: Option<Box<Backup>>,
+    search: JournalSearch,
+    journal_cache: JournalCache,
+    // history of the connected device, shown instead of the dump when toggled
+    archive: Option<JournalArchive>,
+    show_archive: bool,
+    // card holder names of the connected device
+    roster: Option<Roster>,
+    // PIN given to cards enrolled from the journal
+    enroll_pin: String,
+    export: JournalExport,
 
     agrg: Option<String>,
     custom_desc: Option<String>
@@ -80,68 +226,50 @@ impl Application for Agrg {
         let handle = iced::widget::image::Handle::from_memory(bytes);
 
 
-        let mut v = vec![0x00; 16];
-        v.resize(0x1000, 0xff);
-
-        let port = utils::scan_ports();
-        (
-            Self {
-                logo: handle,
-                admin_paswd: String::new(),
-                keepalive: false,
-                agrg: match port {
-                    None => None,
-                    Some(_) => utils::agrg_text_info()
-                },
-                custom_desc: match port {
-                    None => None,
-                    Some(_) => utils::get_text()
-                },
-
-                connected: port.is_some(),
-
-                tab: Tab::Journal,
-                ports: match utils::get_available_ports() {
-                    None => vec![String::from("Нет доступных портов")],
-                    Some(ports) => ports
-                },
-                port: port,
-                data: v,
-                //time: String::new()
-                time: Local::now().format("%H:%M:%S %d.%m.%Y").to_string(),
-                settings_map: vec![
-                    // mode
-                    vec![
-                        "Считыватель".into(),
-                        "Автономный контроллер".into()
-                    ],
-                    
-                    // pinpad mode
-                    vec![
-                        "Wiegand6".into(),
-                        "Wiegand26(hex)".into(),
-                        "Wiegand26(dec)".into(),
-                        "OFF".into()
-                    ],
-
-                    // card reader mode
-                    vec![
-                        "Wiegand26".into(),
-                        "Wiegand34".into(),
-                        "OFF".into()
-                    ],
-
-                    // auto access mode
-                    vec![
-                        "PIN или UID".into(),
-                        "PIN".into(),
-                        "UID".into(),
-                        "PIN и UID".into()
-                    ]
-                ]
-            },
-            iced::Command::none()
-        )
+        let found = utils::scan_ports();
+        let port = found.as_ref().map(|t| t.name().to_string());
+        let mut transport: Option<Box<dyn Transport>> = found
+            .map(|t| Box::new(t) as Box<dyn Transport>);
+        let mut app = Self {
+            logo: handle,
+            admin_paswd: String::new(),
+            keepalive: false,
+            agrg: transport.as_mut().and_then(|t| utils::agrg_text_info(t.as_mut())),
+            custom_desc: transport.as_mut().and_then(|t| utils::get_text(t.as_mut())),
+            transport: transport.map(|t| Arc::new(Mutex::new(t))),
+            job: None,
+            error: None,
+            retry: RetryPolicy::from_env(),
+            pending_dump: None,
+            pending_upload: None,
+            verify: true,
+            rewrite_mismatched: true,
+            snapshot: None,
+            upload_preview: None,
+            restore: None,
+            search: JournalSearch::default(),
+            journal_cache: JournalCache {
+                ordered: utils::journal::order(MemoryImage::default().journal()),
+                shown: Vec::new(),
+                counts: FilterCounts::default(),
+                stats: JournalStats::default(),
+            },
+            archive: None,
+            show_archive: false,
+            roster: None,
+            enroll_pin: String::new(),
+            export: JournalExport::default(),
+
+            tab: Tab::Journal,
+            ports: available_ports(),
+            port,
+            data: MemoryImage::default(),
+            //time: String::new()
+            time: Local::now().format("%H:%M:%S %d.%m.%Y").to_string(),
+        };
+        app.open_archive();
+
+        (app, iced::Command::none())
     }
 
     fn title(&self) -> String {
@@ -157,29 +285,24 @@ impl Application for Agrg {
     fn update(&mut self, message: Self::Message) -> iced::Command<Self::Message> {
         match message {
             AgrgMsg::SaveCustomData => {
-                let mut replacements: Vec<u8> = self.custom_desc.clone().unwrap().into_bytes();
-                
-                // if more that 64 bytes, take the first 64
-                if replacements.len() > 64 {
-                    replacements = replacements[..64].to_vec();
-                }
-                else {
-                    // pad replacements to 64 bytes
-                    replacements.resize(64, 0xff);
-                }
-
-                // send using utils::set_text()
-                utils::set_text(replacements)
+                let text = self.custom_desc.clone().unwrap_or_default();
 
+                let res = match self.device() {
+                    Some(mut transport) => utils::write_text(transport.as_mut(), &text),
+                    None => return iced::Command::none(),
+                };
+                if let Err(e) = res {
+                    self.show_error("Не удалось сохранить описание", e);
+                }
             },
             AgrgMsg::CustomDataChange(str) => {
                 self.custom_desc = Some(str);
             },
             AgrgMsg::PingKeepAlive => {
                 println!("попытка пинга..");
-                if self.keepalive {
+                if let (true, Some(mut transport)) = (self.keepalive, self.device()) {
                     println!("успешно");
-                    _ = utils::get_datetime();
+                    _ = utils::get_datetime(transport.as_mut());
                 }
             }
             AgrgMsg::ToggleKeepAlive => {
@@ -187,188 +310,383 @@ impl Application for Agrg {
                 let current = self.keepalive;
                 self.keepalive = !current;
             },
+            AgrgMsg::ToggleVerify => self.verify = !self.verify,
+            AgrgMsg::ToggleRewrite => self.rewrite_mismatched = !self.rewrite_mismatched,
             AgrgMsg::AdminPasswdEdited(str) => {
                 let cleaned = sanitize_admin_passwd(&str, 6);
                 let replacements: Vec<u8> = cleaned.chars()
-                    .map(|c| c.to_digit(10).expect("Некорректный символ") as u8)
+                    .filter_map(|c| c.to_digit(10))
+                    .map(|d| d as u8)
                     .collect();
-            
-                for (i, &new_byte) in replacements.iter().enumerate() {
-                    if i < 6 { // Ensure we only write 6 digits
-                        self.data[0x0A + i] = new_byte;
-                    }
+
+                if let Err(e) = self.data.set_admin_pin(&replacements) {
+                    self.show_error("Некорректный PIN администратора", e);
                 }
                 // Update the admin_paswd field with the cleaned value
                 self.admin_paswd = cleaned;
             },
             
             AgrgMsg::CardsTab => self.tab = Tab::Cards,
-            AgrgMsg::CardEdited(chunk_index, is_uid, value) => {
-                let base_address = 0x0010 + chunk_index * 16;
-    
-                // get target byte range
-                let start = if is_uid {
-                    base_address
+            AgrgMsg::CardEdited(slot, is_uid, value) => {
+                let res = if is_uid {
+                    self.data.set_card_rfid(slot, &value)
                 } else {
-                    base_address + 10
+                    self.data.set_card_pin(slot, &value)
                 };
-    
-                // convert hex string to bytes
-                let required_length = if is_uid { 10 } else { 6 };
-                let parsed_bytes = if is_uid {
-                    utils::cards::rfid_to_bytes(value).expect("некорректный формат")
-                } else {
-                    utils::cards::pin_to_bytes(value).expect("некорректный формат")
-                };
-
-                if parsed_bytes.len() == required_length {
-                    // update the data vector directly
-                    for (i, byte) in parsed_bytes.iter().enumerate() {
-                        if let Some(pos) = self.data.get_mut(start + i) {
-                            *pos = *byte;
-                        }
-                    }
+                if let Err(e) = res {
+                    self.show_error("Некорректный формат", e);
                 }
             },
             AgrgMsg::ImportCards => {
-                let new_data = match utils::cards::import_bin() {
-                    Ok(res) => res,
-                    Err(_) => { 
-                        println!("Не удалось импортировать данные пользователей");
-                        Vec::new()
+                let res = utils::cards::import_bin()
+                    .and_then(|new_data| match new_data {
+                        Some(new_data) => self.data.set_cards_raw(&new_data),
+                        None => Ok(()),
+                    });
+
+                if let Err(e) = res {
+                    self.show_error("Некорректный/Поврежденный файл", e);
+                }
+            },
+            AgrgMsg::ImportCardTable(mode) => {
+                let rows = match utils::cards::import_table() {
+                    Ok(Some(rows)) => rows,
+                    Ok(None) => return iced::Command::none(),
+                    Err(e) => {
+                        self.show_error("Некорректный файл пользователей", e);
+                        return iced::Command::none();
                     }
                 };
-                
-                if new_data.len() != 255*16 {
-                    panic!("Некорректный/Поврежденный файл");
-                }
 
-                if self.data.len() < 256*16 {
-                    self.data.resize(256*16, 0xff);
+                let imported = match self.data.import_cards(&rows, mode) {
+                    Ok(imported) => imported,
+                    Err(e) => {
+                        self.show_error("Не удалось импортировать пользователей", e);
+                        return iced::Command::none();
+                    }
+                };
+                if let Some(roster) = &mut self.roster {
+                    roster.import(&imported, mode);
+                    if let Err(e) = roster.save() {
+                        self.show_error("Не удалось сохранить имена пользователей", e);
+                    }
+                }
+                if !imported.skipped.is_empty() {
+                    let uids: Vec<String> = imported.skipped.iter().map(|row| row.uid.to_uppercase()).collect();
+                    self.show_error("Уже записаны, пропущены", uids.join(", "));
                 }
-
-                self.data[0x0010..0x1000].copy_from_slice(&new_data);
-                
             },
             AgrgMsg::JournalTab => self.tab = Tab::Journal,
+            AgrgMsg::StatisticsTab => self.tab = Tab::Statistics,
             AgrgMsg::SettingsTab => self.tab = Tab::Settings,
             AgrgMsg::ExportSettings => {
-                _ = utils::settings::export_bin(self.data[0x0000..=0x000f].to_vec(), self.custom_desc.clone().unwrap());
+                if let Err(e) = utils::settings::export_bin(self.data.settings().to_vec(), self.custom_desc.clone().unwrap_or_default(), self.agrg.clone()) {
+                    self.show_error("Не удалось сохранить настройки", e);
+                }
             },
             AgrgMsg::ImportSettings => {
                 let new_data = match utils::settings::import_bin() {
-                    Ok(res) => res,
-                    Err(_) => { 
-                        println!("Не удалось импортировать настройки");
-                        vec![0; 16]
+                    Ok(Some(res)) => res,
+                    Ok(None) => return iced::Command::none(),
+                    Err(e) => {
+                        self.show_error("Не удалось импортировать настройки", e);
+                        return iced::Command::none();
                     }
                 };
 
-                if new_data.len() != 16 {
-                    panic!("Некорректный/Поврежденный файл настроек");
+                match DeviceSettings::parse(&new_data) {
+                    Ok(settings) => {
+                        self.admin_paswd = settings.admin_pin_string();
+                        self.data.set_device_settings(&settings);
+                    },
+                    Err(e) => self.show_error("Некорректный/Поврежденный файл настроек", e),
                 }
-
-                if self.data.len() < 16 {
-                    self.data.resize(16, 0xff);
-                }
-
-                self.data[0x0000..0x0010].copy_from_slice(&new_data);
             },
-            AgrgMsg::SettingsUpdate(addr, val) => {
-                let setting_index = addr;
-                self.data[addr] = self.search(setting_index, &val);
+            AgrgMsg::SettingChanged(index, value) => {
+                if let Err(e) = self.data.set_setting(index, value) {
+                    self.show_error("Некорректный параметр", e);
+                }
             },
+            AgrgMsg::SerialChoice(_) if self.job.is_some() => self.show_error("Порт не изменен", "дождитесь окончания обмена"),
             AgrgMsg::SerialChoice(s) => { 
-                self.port = Some(s); 
-                utils::set_port(self.port.clone().expect("Порты не найдены")); 
-                self.agrg = utils::agrg_text_info();
-                self.custom_desc = utils::get_text();
-                self.connected = utils::check_handle(self.port.clone().unwrap())
+                let mut transport: Option<Box<dyn Transport>> = if s == utils::sim::DEMO_PORT {
+                    Some(Box::new(utils::sim::Simulator::demo()))
+                } else {
+                    utils::check_handle(&s).map(|t| Box::new(t) as Box<dyn Transport>)
+                };
+                if transport.is_none() {
+                    self.show_error(&s, "ручка не отвечает");
+                }
+                self.port = Some(s);
+                self.pending_dump = None;
+                self.pending_upload = None;
+                self.snapshot = None;
+                self.agrg = transport.as_mut().and_then(|t| utils::agrg_text_info(t.as_mut()));
+                self.custom_desc = transport.as_mut().and_then(|t| utils::get_text(t.as_mut()));
+                self.transport = transport.map(|t| Arc::new(Mutex::new(t)));
+                self.open_archive();
             },
             AgrgMsg::RefreshPorts => {
-                self.ports = match utils::get_available_ports() {
-                    None => vec![String::from("Порты не найдены")],
-                    Some(ports) => ports
-                }
+                self.ports = available_ports()
             },
             AgrgMsg::ExportJournal => {
-                let journal_entries: Vec<Option<(String, String)>> = self.data[0x1000..self.data.len()]
-                    .chunks(16) 
-                    .map(|chunk| utils::journal::journal_entry_to_string(
-                        utils::journal::parse_journal_entry(chunk.to_vec()).expect("ошибка при обработке строки журнала")
-                        )
-                    )
-                    .collect();
+                let names = Names::new(self.roster.as_ref(), &self.data);
+                let info = ReportInfo::now(self.agrg.clone(), self.custom_desc.clone());
+                let res = utils::journal::serializer(&self.journal_cache.shown, &names, self.export.format, self.export.csv, &info);
 
-                _ = utils::journal::serializer(journal_entries);
+                if let Err(e) = res {
+                    self.show_error("Не удалось сохранить журнал", e);
+                }
+            },
+            AgrgMsg::ExportReport => {
+                let names = Names::new(self.roster.as_ref(), &self.data);
+                let res = utils::stats::save_report(&self.journal_cache.stats, &names);
+
+                if let Err(e) = res {
+                    self.show_error("Не удалось сохранить отчёт", e);
+                }
             },
             AgrgMsg::ExportCards => {
-                _ = utils::cards::export_bin(self.data[0x0010..=0x0fff].to_vec(), self.custom_desc.clone().unwrap());
+                if let Err(e) = utils::cards::export_bin(self.data.cards_raw().to_vec(), self.custom_desc.clone().unwrap_or_default(), self.agrg.clone()) {
+                    self.show_error("Не удалось сохранить пользователей", e);
+                }
             },
-            AgrgMsg::MemDump => {
-                // self.time = match utils::get_datetime() {
-                //     Ok(res) => res,
-                //     Err(_) => "Error".to_string()
-                // };
-                let current = self.keepalive.clone();
-                if current {
-                    self.keepalive = false;
+            AgrgMsg::ExportCardTable(format) => {
+                let rows = utils::roster::card_table(&self.data, self.roster.as_ref());
+                if let Err(e) = utils::cards::export_table(&rows, format, self.custom_desc.clone().unwrap_or_default()) {
+                    self.show_error("Не удалось сохранить пользователей", e);
                 }
-
-                self.data = vec![];
-
-                
-                self.data = match utils::mem_dump() {
-                    Ok(data) => data,
-                    Err(_) => {
-                        println!("Порт неверный - нет данных");
-                        Vec::new()
-                    }
+            },
+            AgrgMsg::DismissError => self.error = None,
+            AgrgMsg::FilterFrom(from) => {
+                self.search.filter.from = parse_date(&from);
+                self.search.from = from;
+                self.refresh_journal();
+            },
+            AgrgMsg::FilterTo(to) => {
+                self.search.filter.to = parse_date(&to);
+                self.search.to = to;
+                self.refresh_journal();
+            },
+            AgrgMsg::FilterKind(kind) => {
+                self.search.filter.kind = match kind {
+                    KindChoice::All => None,
+                    KindChoice::Kind(kind) => Some(kind),
                 };
-                // self.data = utils::mock::get_data()
-                self.admin_paswd = self.data[0xA..=0xF].iter().map(|n| n.to_string()).collect();
-                
-                if current {
-                    self.keepalive = true;
+                self.refresh_journal();
+            },
+            AgrgMsg::FilterSlot(slot) => {
+                self.search.filter.slot = slot.trim().parse().ok();
+                self.search.slot = slot;
+                self.refresh_journal();
+            },
+            AgrgMsg::FilterUid(uid) => {
+                self.search.filter.uid = uid;
+                self.refresh_journal();
+            },
+            AgrgMsg::ResetFilter => {
+                self.search = JournalSearch::default();
+                self.refresh_journal();
+            },
+            AgrgMsg::ToggleArchive => {
+                self.show_archive = !self.show_archive;
+                self.refresh_journal();
+            },
+            AgrgMsg::EnrollPinEdited(pin) => self.enroll_pin = pin,
+            AgrgMsg::ExportFormatChosen(format) => self.export.format = format,
+            AgrgMsg::CsvDelimiter(delimiter) => self.export.csv.delimiter = delimiter,
+            AgrgMsg::ToggleBom => self.export.csv.bom = !self.export.csv.bom,
+            AgrgMsg::EnrollUid(uid) => {
+                let card = Card { rfid: uid, pin: self.enroll_pin.clone() };
+                match self.data.enroll(&card) {
+                    Ok(slot) => eprintln!("UID {} записан в слот {}", card.rfid, slot),
+                    Err(e) => self.show_error("Не удалось записать карту", e),
                 }
             },
-            AgrgMsg::MemUpload => {
-                let current = self.keepalive.clone();
-                if current {
-                    self.keepalive = false;
+            AgrgMsg::HolderEdited(slot, holder) => {
+                let Some(roster) = &mut self.roster else { return iced::Command::none() };
+                roster.set(slot, holder);
+                if let Err(e) = roster.save() {
+                    self.show_error("Не удалось сохранить имена пользователей", e);
+                }
+            },
+            AgrgMsg::MemDump => {
+                // continue an interrupted dump instead of starting over
+                let dump = self.pending_dump.take().unwrap_or_else(Transfer::dump);
+                let retry = self.retry;
+
+                return self.spawn_transfer(move |transport, on_block| {
+                    let mut dump = dump;
+                    let res = utils::mem_dump(transport, &retry, &mut dump, on_block);
+                    AgrgMsg::DumpFinished(dump, res.map_err(|e| describe(&e)))
+                });
+            },
+            AgrgMsg::DumpFinished(dump, res) => {
+                self.job = None;
+                print!("{}", dump.report());
+                match res {
+                    Ok(()) => match MemoryImage::from_dump(dump.data) {
+                        Ok(image) => self.load_image(image),
+                        Err(e) => self.show_error("Некорректные данные ручки", e),
+                    },
+                    Err(e) => {
+                        self.show_error("Выгрузка прервана", e);
+                        self.pending_dump = Some(dump);
+                    },
                 }
+            },
+            AgrgMsg::MemUpload => {
+                self.restore = None;
+                let image = self.data.upload_region();
+                self.upload_preview = match &self.snapshot {
+                    None => Some(vec!["Полная запись: данные с ручки не выгружались".into()]),
+                    Some(snapshot) => {
+                        Some(utils::diff::changes(snapshot, image).iter()
+                            .map(|c| c.to_string())
+                            .collect())
+                    },
+                };
+            },
+            AgrgMsg::CancelUpload => {
+                self.upload_preview = None;
+                self.restore = None;
+            },
+            AgrgMsg::ConfirmUpload => {
+                self.upload_preview = None;
 
-                match self.data.as_slice() {
-                    [] => println!("Порт неверный - нет данных"),
-                    _ => {
-                        match utils::mem_upload(self.data[0x0000..0x1000].to_vec()) {
-                            Ok(_) => println!("Обновление данных ручки.."),
-                            Err(_) => {
-                                println!("Порт неверный - нет данных")
+                let (image, text) = match &self.restore {
+                    Some(backup) => (backup.image.upload_region().to_vec(), Some(backup.text.clone())),
+                    None => (self.data.upload_region().to_vec(), None),
+                };
+                // resume only if the image did not change since the failure
+                let upload = match self.pending_upload.take() {
+                    Some(pending) if pending.data == image => pending,
+                    _ => Transfer::upload_diff(image, self.snapshot.clone()),
+                };
+                let (retry, verify, rewrite) = (self.retry, self.verify, self.rewrite_mismatched);
+
+                return self.spawn_transfer(move |transport, on_block| {
+                    let mut upload = upload;
+                    let res = utils::mem_upload(transport, &retry, &mut upload, on_block)
+                        .map(|()| match verify {
+                            true => verify_upload(transport, &retry, &upload.data, rewrite),
+                            false => Verified::Skipped,
+                        })
+                        .and_then(|verified| match &text {
+                            Some(text) => utils::write_text(transport, text).map(|()| verified),
+                            None => Ok(verified),
+                        });
+                    AgrgMsg::UploadFinished(upload, res.map_err(|e| describe(&e)))
+                });
+            },
+            AgrgMsg::UploadFinished(upload, res) => {
+                self.job = None;
+                print!("{}", upload.report());
+                match res {
+                    Ok(verified) => {
+                        println!("Обновление данных ручки..");
+                        match &verified {
+                            Verified::Mismatched(addrs) => {
+                                let addrs: Vec<String> = addrs.iter().map(|addr| format!("{:04X}", addr)).collect();
+                                self.show_error("Проверка записи не пройдена, расхождения по адресам", addrs.join(", "));
+                            },
+                            Verified::Failed(e) => self.show_error("Проверка записи не выполнена", e),
+                            Verified::Skipped | Verified::Passed => {},
+                        }
+                        // settings and cards of a restored backup replace the edited ones
+                        if let Some(backup) = self.restore.take() {
+                            let restored = self.data.set_settings(backup.image.settings())
+                                .and_then(|()| self.data.set_cards_raw(backup.image.cards_raw()));
+                            if let Err(e) = restored {
+                                self.show_error("Некорректная резервная копия", e);
                             }
+                            self.admin_paswd = self.data.admin_pin().iter().map(|n| n.to_string()).collect();
+                            self.custom_desc = Some(backup.text);
+                        }
+                        self.snapshot = verified.stored().then_some(upload.data);
+                    },
+                    Err(e) => {
+                        self.show_error("Загрузка прервана", e);
+                        self.restore = None;
+                        self.pending_upload = Some(upload);
+                    },
+                }
+            },
+            AgrgMsg::MakeBackup => {
+                let retry = self.retry;
+
+                return self.spawn_transfer(move |transport, on_block| {
+                    let mut dump = Transfer::dump();
+                    let res = Backup::read(transport, &retry, &mut dump, on_block);
+                    AgrgMsg::BackupFinished(dump, res.map(Box::new).map_err(|e| describe(&e)))
+                });
+            },
+            AgrgMsg::BackupFinished(dump, res) => {
+                self.job = None;
+                print!("{}", dump.report());
+                match res {
+                    Ok(backup) => {
+                        if let Err(e) = backup::export_bin(&backup) {
+                            self.show_error("Не удалось сохранить резервную копию", e);
                         }
+                        self.custom_desc = Some(backup.text.clone());
+                        self.load_image(backup.image);
+                    },
+                    Err(e) => self.show_error("Резервная копия не снята", e),
+                }
+            },
+            AgrgMsg::Restore => {
+                let backup = match backup::import_bin() {
+                    Ok(Some(backup)) => backup,
+                    Ok(None) => return iced::Command::none(),
+                    Err(e) => {
+                        self.show_error("Некорректная/Поврежденная резервная копия", e);
+                        return iced::Command::none();
                     }
+                };
+
+                let mut changes = backup.changes(self.snapshot.as_deref(), self.custom_desc.as_deref().unwrap_or_default());
+                // nothing gets written, so the source does not matter
+                if !changes.is_empty() && backup.device != self.agrg {
+                    let device = backup.device.as_deref().unwrap_or("неизвестно").replace('\n', " ");
+                    changes.insert(0, format!("Копия снята с другой ручки: {}", device));
                 }
-                
-                if current {
-                    self.keepalive = true;
+                self.upload_preview = Some(changes);
+                self.restore = Some(Box::new(backup));
+            },
+            AgrgMsg::TransferProgress(progress) => {
+                if let Some(job) = &mut self.job {
+                    job.progress = progress;
+                }
+            },
+            AgrgMsg::CancelTransfer => {
+                if let Some(job) = &self.job {
+                    job.cancel.store(true, Ordering::Relaxed);
                 }
-            }, 
+            },
             AgrgMsg::TimeSync => {
-                self.time = Local::now().format("%H:%M:%S %d.%m.%Y").to_string(); 
-                _ = utils::set_datetime(self.time.clone())
+                let time = Local::now().format("%H:%M:%S %d.%m.%Y").to_string();
+                let res = match self.device() {
+                    Some(mut transport) => utils::set_datetime(transport.as_mut(), time.clone()),
+                    None => return iced::Command::none(),
+                };
+                // show the time only once the handle has it
+                match res {
+                    Ok(_) => self.time = time,
+                    Err(e) => self.show_error("Не удалось установить время", e),
+                }
             }
         }
         iced::Command::none()
     } 
 
-    fn view(&self) -> iced::Element<Self::Message> {
+    fn view(&self) -> iced::Element<'_, Self::Message> {
         column![
             // connection header
             row![
                 row![
                     Text::new("o").style(
-                        if self.connected { 
+                        if self.transport.is_some() { 
                             iced::Color::from_rgb(0.0, 1.0, 0.0) 
                         } else { 
                             iced::Color::from_rgb(1.0, 0.0, 0.0)
@@ -410,16 +728,34 @@ impl Application for Agrg {
             Space::new(0, 20),
 
             row![
-                button("Выгрузка v").on_press_maybe(if self.connected { Some(AgrgMsg::MemDump) } else { None } ),
+                button("Выгрузка v").on_press_maybe(if self.idle() { Some(AgrgMsg::MemDump) } else { None } ),
 
-                button("Загрузка ^").on_press_maybe(if self.connected { Some(AgrgMsg::MemUpload) } else { None } )
-            ].spacing(20),
+                button("Загрузка ^").on_press_maybe(if self.idle() { Some(AgrgMsg::MemUpload) } else { None } ),
+
+                button("Резервная копия").on_press_maybe(if self.idle() { Some(AgrgMsg::MakeBackup) } else { None } ),
+
+                button("Восстановить").on_press_maybe(if self.idle() { Some(AgrgMsg::Restore) } else { None } ),
+
+                Toggler::new(Some("Проверка записи".into()), self.verify, |_| { AgrgMsg::ToggleVerify }).width(Length::Shrink),
+                Toggler::new(Some("Перезапись расхождений".into()), self.rewrite_mismatched, |_| { AgrgMsg::ToggleRewrite }).width(Length::Shrink)
+            ].spacing(20).align_items(Alignment::Center),
 
             Space::new(0, 20),
+
+            match &self.job {
+                Some(job) => transfer_progress(job.progress),
+                None => Space::new(0, 0).into(),
+            },
+
+            match &self.error {
+                Some(error) => error_banner(error),
+                None => Space::new(0, 0).into(),
+            },
             
             container(
                 row![
                     button("Журнал").on_press(AgrgMsg::JournalTab),
+                    button("Статистика").on_press(AgrgMsg::StatisticsTab),
                     button("Пользователи").on_press(AgrgMsg::CardsTab),
                     button("Параметры").on_press(AgrgMsg::SettingsTab)
                 ].spacing(20),
@@ -427,17 +763,23 @@ impl Application for Agrg {
 
             Space::new(0, 20),
 
-            match self.tab {
-                Tab::Journal => {
-                    journal(self.data.clone())
+            match (&self.upload_preview, self.tab) {
+                (Some(changes), _) => upload_preview(changes),
+
+                (None, Tab::Journal) => {
+                    journal(&Names::new(self.roster.as_ref(), &self.data), &self.search, self.archive.as_ref().filter(|_| self.show_archive), &self.journal_cache, self.archive.is_some(), &self.enroll_pin, self.export)
                 },
                 
-                Tab::Cards => {
-                    cards(self.data.clone())
+                (None, Tab::Statistics) => {
+                    statistics(&self.journal_cache.stats, &Names::new(self.roster.as_ref(), &self.data))
+                },
+
+                (None, Tab::Cards) => {
+                    cards(&self.data, self.roster.as_ref())
                 },
 
-                Tab::Settings => {
-                    settings(self.data.clone(), &self.settings_map, self.time.clone(), self.custom_desc.clone(), self.admin_paswd.clone(), self.logo.clone())
+                (None, Tab::Settings) => {
+                    settings(&self.data, self.time.clone(), self.custom_desc.clone(), self.admin_paswd.clone(), self.logo.clone(), self.idle())
                 }
             },
         ].width(Length::Fill).padding(20)
@@ -450,141 +792,451 @@ impl Application for Agrg {
 }
 
 impl Agrg {
-    fn search(&self, setting_index: usize, val: &String) -> u8 {
-        if let Some(options) = self.settings_map.get(setting_index) {
-            for (id, entry) in options.iter().enumerate() {
-                if val == entry {
-                    return id as u8;
-                }
-            }
+    // banner above the tabs until dismissed, the console keeps the full log
+    fn show_error(&mut self, context: &str, e: impl std::fmt::Display) {
+        println!("{}: {}", context, e);
+        self.error = Some(format!("{}: {}", context, e));
+    }
+
+    // freshly read memory becomes both the edited image and the upload baseline
+    fn load_image(&mut self, image: MemoryImage) {
+        self.snapshot = Some(image.upload_region().to_vec());
+        self.admin_paswd = image.admin_pin().iter().map(|n| n.to_string()).collect();
+        self.data = image;
+        self.journal_cache.ordered = utils::journal::order(self.data.journal());
+        self.archive_journal();
+        self.refresh_journal();
+    }
+
+    // journal or archive as the journal tab shows it, filters applied
+    fn refresh_journal(&mut self) {
+        let entries = match (&self.archive, self.show_archive) {
+            (Some(archive), true) => archive.entries(),
+            _ => &self.journal_cache.ordered.entries[..],
+        };
+        self.journal_cache.counts = self.search.filter.counts(entries);
+        self.journal_cache.shown = self.search.filter.apply(entries);
+        self.journal_cache.stats = JournalStats::new(&self.journal_cache.shown);
+    }
+
+    // archive and roster of the connected device, None without a device info string
+    fn open_archive(&mut self) {
+        self.show_archive = false;
+        self.archive = match self.agrg.as_deref().map(JournalArchive::open) {
+            Some(Ok(archive)) => Some(archive),
+            Some(Err(e)) => {
+                self.show_error("Не удалось открыть архив журнала", e);
+                None
+            },
+            None => None,
+        };
+        self.roster = match self.agrg.as_deref().map(Roster::open) {
+            Some(Ok(roster)) => Some(roster),
+            Some(Err(e)) => {
+                self.show_error("Не удалось открыть имена пользователей", e);
+                None
+            },
+            None => None,
+        };
+        self.refresh_journal();
+    }
+
+    // adds the freshly dumped journal to the archive
+    fn archive_journal(&mut self) {
+        let Some(archive) = &mut self.archive else { return };
+
+        match archive.merge(&self.journal_cache.ordered.entries) {
+            Ok(added) => eprintln!("{}: новых записей {}", archive.path().display(), added),
+            Err(e) => self.show_error("Не удалось дополнить архив журнала", e),
         }
-        0 
+    }
+
+    // device for a quick request, None while a transfer holds it
+    fn device(&self) -> Option<MutexGuard<'_, Box<dyn Transport>>> {
+        match self.transport.as_ref()?.try_lock() {
+            Ok(guard) => Some(guard),
+            // a panicked transfer leaves the port itself usable
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    fn idle(&self) -> bool {
+        self.transport.is_some() && self.job.is_none()
+    }
+
+    // runs `work` on its own thread, streaming progress and the final message back
+    fn spawn_transfer(
+        &mut self,
+        work: impl FnOnce(&mut dyn Transport, &mut dyn FnMut(Progress) -> bool) -> AgrgMsg + Send + 'static,
+    ) -> iced::Command<AgrgMsg> {
+        let (Some(transport), None) = (self.transport.clone(), &self.job) else {
+            self.show_error("Порт неверный", "нет данных");
+            return iced::Command::none();
+        };
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.job = Some(Job { progress: Progress::default(), cancel: cancel.clone() });
+
+        iced::command::channel(64, move |mut sender| async move {
+            std::thread::spawn(move || {
+                let mut progress_sender = sender.clone();
+                let mut on_block = |progress| {
+                    // progress updates may be dropped when the UI lags behind, the result may not
+                    _ = progress_sender.try_send(AgrgMsg::TransferProgress(progress));
+                    !cancel.load(Ordering::Relaxed)
+                };
+
+                let mut transport = transport.lock().unwrap_or_else(PoisonError::into_inner);
+                let finished = work(transport.as_mut(), &mut on_block);
+                _ = iced::futures::executor::block_on(sender.send(finished));
+            });
+        })
+    }
+}
+
+// reads the uploaded ranges back and optionally rewrites what differs, true if all match
+fn verify_upload(transport: &mut dyn Transport, retry: &RetryPolicy, image: &[u8], rewrite: bool) -> Verified {
+    let mut mismatched = match utils::verify_upload(transport, retry, image) {
+        Ok(addrs) => addrs,
+        Err(e) => return Verified::Failed(describe(&e)),
+    };
+
+    if !mismatched.is_empty() && rewrite {
+        println!("Перезапись {} блоков", mismatched.len());
+        let rewritten = utils::rewrite_blocks(transport, retry, image, &mismatched)
+            .and_then(|_| utils::verify_upload(transport, retry, image));
+        match rewritten {
+            Ok(addrs) => mismatched = addrs,
+            Err(e) => return Verified::Failed(format!("перезапись: {}", describe(&e))),
+        }
+    }
+
+    match mismatched.is_empty() {
+        true => Verified::Passed,
+        false => Verified::Mismatched(mismatched),
+    }
+}
+
+fn describe(e: &AgrgError) -> String {
+    match e.is_cancelled() {
+        true => "отменено пользователем".into(),
+        false => e.to_string(),
     }
 }
 
 // tab ui functions
-fn journal(data: Vec<u8>) -> iced::Element<'static, AgrgMsg> {
-    match data.len() {
-        0..=0x1000 => Text::new("Нет данных").height(Length::Fill).into(),
-        _ => {    
-            let journal_entries: Vec<(String, String)> = data[0x1000..data.len()]
-                .chunks(16)
-                .map(|chunk| {
-                    utils::journal::journal_entry_to_string(utils::journal::parse_journal_entry(chunk.to_vec()).expect("asdasd"))
-                })
-                .filter(|chunk| {
-                    chunk.is_some()
-                })
-                .map(|chunk| {
-                    chunk.unwrap()
-                })
-                .collect();
-            
+fn error_banner(error: &str) -> iced::Element<'static, AgrgMsg> {
+    container(
+        row![
+            Text::new(error.to_string()).style(Color::from_rgb(1.0, 0.3, 0.3)).width(Length::Fill),
+            button("Закрыть").on_press(AgrgMsg::DismissError)
+        ].spacing(20).align_items(Alignment::Center)
+    ).padding(10).width(Length::Fill).style(iced::theme::Container::Box)
+    .into()
+}
+
+fn transfer_progress(progress: Progress) -> iced::Element<'static, AgrgMsg> {
+    let total = progress.blocks_total.max(1) as f32;
+
+    row![
+        progress_bar(0.0..=total, progress.blocks_done as f32).width(400).height(20),
+        Text::new(format!("блоков {}/{}, {} байт", progress.blocks_done, progress.blocks_total, progress.bytes_done)),
+        button("Отмена").on_press(AgrgMsg::CancelTransfer)
+    ].spacing(20).align_items(Alignment::Center).into()
+}
 
-            // two columns for data
-            let mut left_col: Column<AgrgMsg> = Column::new()
-                .spacing(10)
-                .align_items(Alignment::Start);
-            let mut right_col: Column<AgrgMsg> = Column::new()
-                .spacing(10)
-                .align_items(Alignment::Start);
-
-            // headers
-            left_col = left_col.push(Text::new("Дата").width(Length::Fill));
-            right_col = right_col.push(Text::new("Событие").width(Length::Fill));
-
-            // populate the columns
-            for row in journal_entries {
-                left_col = left_col.push(Text::new(row.0.clone()).width(Length::Fill));
-                right_col = right_col.push(Text::new(row.1.clone()).width(Length::Fill));
+// an empty list means the device already holds the data
+fn upload_preview(changes: &[String]) -> iced::Element<'static, AgrgMsg> {
+    let mut rows = Column::new().spacing(10);
+    for change in changes {
+        rows = rows.push(Text::new(change.clone()));
+    }
+    if changes.is_empty() {
+        rows = rows.push(Text::new("Нет изменений"));
+    }
+
+    container(
+        column![
+            Text::new("Будут записаны изменения:"),
+            scrollable(rows).height(Length::Fill),
+            row![
+                button("Записать").on_press_maybe(if changes.is_empty() { None } else { Some(AgrgMsg::ConfirmUpload) }),
+                button("Отмена").on_press(AgrgMsg::CancelUpload)
+            ].spacing(20)
+        ].spacing(20)
+    ).padding(10)
+    .into()
+}
+
+// `shown` is the archive while it takes the place of the dumped journal
+fn journal(names: &Names, search: &JournalSearch, shown: Option<&JournalArchive>, cache: &JournalCache, has_archive: bool, enroll_pin: &str, export: JournalExport) -> iced::Element<'static, AgrgMsg> {
+    let (total, status) = match shown {
+        Some(archive) => (
+            archive.entries().len(),
+            Text::new(format!("Архив: {} записей, {}", archive.entries().len(), archive.path().display())).into(),
+        ),
+        None => (cache.ordered.entries.len(), ring_status(&cache.ordered)),
+    };
+
+    let mut buttons = row![
+        // exporn btn
+        button("Экспорт").on_press(AgrgMsg::ExportJournal),
+        pick_list(&ExportFormat::ALL[..], Some(export.format), AgrgMsg::ExportFormatChosen),
+    ].spacing(10).align_items(Alignment::Center);
+
+    // only CSV has a layout to choose
+    if export.format == ExportFormat::Csv {
+        buttons = buttons
+            .push(pick_list(&Delimiter::ALL[..], Some(export.csv.delimiter), AgrgMsg::CsvDelimiter))
+            .push(Toggler::new(Some("BOM для Excel".into()), export.csv.bom, |_| AgrgMsg::ToggleBom).width(Length::Shrink));
+    }
+
+    let buttons = buttons
+        .push(button(if shown.is_some() { "Журнал ручки" } else { "Архив" })
+            .on_press_maybe(if has_archive { Some(AgrgMsg::ToggleArchive) } else { None }))
+        .push(text_input("PIN при записи", enroll_pin)
+            .on_input(|v| AgrgMsg::EnrollPinEdited(sanitize_pin(&v, 6)))
+            .width(150));
+
+    if total == 0 {
+        return column![buttons, Text::new("Нет данных").height(Length::Fill)].spacing(10).padding(10).into();
+    }
+
+    // header row
+    let mut data_rows: Column<AgrgMsg> = Column::new()
+        .spacing(10)
+        .align_items(Alignment::Start)
+        .push(row![
+            Text::new("Дата").width(180),
+            Text::new("Событие").width(Length::Fill),
+        ].spacing(30));
+
+    // one row per entry, refused cards can be enrolled right away
+    for entry in &cache.shown {
+        let mut entry_row: Row<AgrgMsg> = row![
+            Text::new(entry.time.format(utils::journal::TIME_FORMAT).to_string()).width(180),
+            Text::new(names.describe(&entry.event)).width(Length::Fill),
+        ].spacing(30).align_items(Alignment::Center);
+
+        if let JournalEvent::UnknownUid { uid } = &entry.event {
+            if names.enrolled_slot(uid).is_none() {
+                entry_row = entry_row.push(button("Записать").on_press(AgrgMsg::EnrollUid(uid.clone())));
             }
+        }
+        data_rows = data_rows.push(entry_row);
+    }
 
-            // combine columns into a row
-            let data_row: Row<AgrgMsg> = Row::new()
-                .spacing(30)
-                .push(left_col)
-                .push(right_col)
-                .into();
+    container(
+        column![
+            buttons,
+            status,
+            journal_filters(search, cache.counts, total),
+
+            // rows with content
+            scrollable(
+                data_rows
+            ).height(Length::Fill)
+        ].spacing(10)
+    ).padding(10)
+    .into()
+}
 
-            container(
+fn statistics(stats: &JournalStats, names: &Names) -> iced::Element<'static, AgrgMsg> {
+    if stats.total == 0 {
+        return Text::new("Нет данных").height(Length::Fill).into();
+    }
+
+    let chart = |title: &str, bars: Vec<(String, usize)>| column![
+        Text::new(title.to_string()),
+        canvas(BarChart::new(bars)).width(Length::Fill).height(160),
+    ].spacing(5);
+
+    let mut summary = Column::new().spacing(5);
+    if let (Some(first), Some(last)) = (stats.first, stats.last) {
+        summary = summary.push(Text::new(format!(
+            "Период: {} - {}",
+            first.format(utils::journal::TIME_FORMAT),
+            last.format(utils::journal::TIME_FORMAT)
+        )));
+    }
+    for (label, value) in stats.summary() {
+        summary = summary.push(Text::new(format!("{}: {}", label, value)));
+    }
+
+    let days = stats.per_day.iter().map(|(day, count)| (day.format("%d.%m").to_string(), *count)).collect();
+    let hours = stats.per_hour.iter().enumerate().map(|(hour, count)| (hour.to_string(), *count)).collect();
+    let slots = stats.per_slot.iter().map(|(slot, count)| (slot.to_string(), *count)).collect();
+
+    // names don't fit under the bars, list them instead
+    let mut holders = Column::new().spacing(5);
+    for (slot, count) in &stats.per_slot {
+        holders = holders.push(Text::new(format!("{}: {}", names.slot(*slot as usize), count)));
+    }
+
+    container(
+        column![
+            row![
+                button("Экспорт отчёта").on_press(AgrgMsg::ExportReport),
+                Text::new("С учётом фильтров журнала"),
+            ].spacing(10).align_items(Alignment::Center),
+            scrollable(
                 column![
-                    // exporn btn
-                    button("Экспорт CSV").on_press(AgrgMsg::ExportJournal),
-
-                    // row of coluimns with content
-                    scrollable(
-                        data_row
-                    ).height(Length::Fill)
-                ]
-            ).padding(10)
-            .into()
-        }
+                    summary,
+                    chart("События по дням", days),
+                    chart("События по часам", hours),
+                    chart("Проходы по пользователям", slots),
+                    holders,
+                ].spacing(20)
+            ).height(Length::Fill),
+        ].spacing(10)
+    ).padding(10)
+    .into()
+}
+
+// where the ring stands and what looks wrong in it
+fn ring_status(journal: &OrderedJournal) -> iced::Element<'static, AgrgMsg> {
+    let mut status = Column::new().spacing(5).push(Text::new(format!(
+        "Записей: {}, следующая запись: {:04X}{}",
+        journal.entries.len(),
+        journal.head,
+        if journal.wrapped { ", журнал заполнен, старые записи перезаписываются" } else { "" }
+    )));
+    for issue in &journal.issues {
+        status = status.push(Text::new(issue.to_string()).style(Color::from_rgb(1.0, 0.6, 0.2)));
     }
-}   
-
-fn cards(data: Vec<u8>) -> iced::Element<'static, AgrgMsg> {
-    match data.as_slice() {
-        [] => column![ 
-                Text::new("Нет данных").height(Length::Fill),
-                button("Импортировать").on_press(AgrgMsg::ImportCards)
-            ].into(),
-        _ => {
-            let chunks: Vec<(String, String)> = data[0x0010..=0x0fff]
-                .chunks(16)
-                .map(|chunk| {
-                    let card = utils::cards::parse(chunk.to_vec()).expect("Invalid card data");
-                    (card.rfid, card.pin)
-                })
-                .collect();
+    status.into()
+}
 
-            // header row
-            let header = row![
-                Text::new("№").width(20),
-                Text::new("UID").width(200),
-                Text::new("PIN").width(120),
-            ].spacing(20);
+fn journal_filters(search: &JournalSearch, counts: FilterCounts, total: usize) -> iced::Element<'static, AgrgMsg> {
+    let matches = |count: usize| Text::new(format!("совпадений: {}", count));
+    let kind_choices: Vec<KindChoice> = std::iter::once(KindChoice::All)
+        .chain(EventKind::ALL.into_iter().map(KindChoice::Kind))
+        .collect();
 
-            // card rows
-            let mut card_rows = Column::new()
-                .spacing(10)
-                .push(header);
+    column![
+        row![
+            text_input("с ГГГГ-ММ-ДД", &search.from).on_input(AgrgMsg::FilterFrom).width(150),
+            text_input("по ГГГГ-ММ-ДД", &search.to).on_input(AgrgMsg::FilterTo).width(150),
+            matches(counts.dates),
+        ].spacing(10).align_items(Alignment::Center),
+        row![
+            pick_list(kind_choices, search.filter.kind.map(KindChoice::Kind), AgrgMsg::FilterKind)
+                .placeholder("Тип события").width(310),
+            matches(counts.kind),
+        ].spacing(10).align_items(Alignment::Center),
+        row![
+            text_input("Пользователь", &search.slot).on_input(AgrgMsg::FilterSlot).width(150),
+            text_input("Часть UID", &search.filter.uid).on_input(AgrgMsg::FilterUid).width(150),
+            matches(counts.slot),
+            matches(counts.uid),
+        ].spacing(10).align_items(Alignment::Center),
+        row![
+            Text::new(format!("Показано {} из {}", counts.all, total)),
+            button("Сбросить фильтры").on_press(AgrgMsg::ResetFilter),
+        ].spacing(10).align_items(Alignment::Center),
+    ].spacing(5).into()
+}
 
-            for (index, chunk) in chunks.iter().enumerate() {
-                let address = 0x0010 + index * 16;
-                let address_text = format!("{}", address/16);
+fn cards(data: &MemoryImage, roster: Option<&Roster>) -> iced::Element<'static, AgrgMsg> {
+    let chunks: Vec<Result<(String, String), String>> = data.cards()
+        .map(|(_, card)| card.map(|card| (card.rfid, card.pin)).map_err(|e| e.to_string()))
+        .collect();
 
-                let card_row = row![
+    // header row
+    let header = row![
+        Text::new("№").width(20),
+        Text::new("UID").width(200),
+        Text::new("PIN").width(120),
+        Text::new("Имя").width(200),
+        Text::new("Примечание").width(200),
+    ].spacing(20);
+
+    // card rows
+    let mut card_rows = Column::new()
+        .spacing(10)
+        .push(header);
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let slot = index + 1;
+        let address_text = format!("{}", slot);
+
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // a broken PIN is the only way a slot fails to parse
+                card_rows = card_rows.push(row![
                     Text::new(address_text).width(20),
-                    text_input(&chunk.0, &chunk.0)
-                        .on_input(move |v| {
-                            let cleaned = sanitize_hex_input(&v, 20);
-                            AgrgMsg::CardEdited(index, true, cleaned)
-                        })
-                        .width(200),
-                    text_input(&chunk.1, &chunk.1)
-                        .on_input(move |v| {
-                            let cleaned = sanitize_pin(&v, 6);
-                            AgrgMsg::CardEdited(index, false, cleaned)
-                        })
-                        .width(120),
-                ].spacing(20);
+                    Text::new(format!("Ошибка: {}", e)).width(200),
+                    button("Сбросить PIN").on_press(AgrgMsg::CardEdited(slot, false, String::new())),
+                ].spacing(20));
+                continue;
+            },
+        };
+
+        let card_row = row![
+            Text::new(address_text).width(20),
+            text_input(&chunk.0, &chunk.0)
+                .on_input(move |v| {
+                    let cleaned = sanitize_hex_input(&v, 20);
+                    AgrgMsg::CardEdited(slot, true, cleaned)
+                })
+                .width(200),
+            text_input(&chunk.1, &chunk.1)
+                .on_input(move |v| {
+                    let cleaned = sanitize_pin(&v, 6);
+                    AgrgMsg::CardEdited(slot, false, cleaned)
+                })
+                .width(120),
+            holder_inputs(slot, roster),
+        ].spacing(20);
 
-                card_rows = card_rows.push(card_row);
-            }
+        card_rows = card_rows.push(card_row);
+    }
 
-            container(
-                row![
-                    column![
-                        button("Экспорт").on_press(AgrgMsg::ExportCards),
-                        button("Импорт").on_press(AgrgMsg::ImportCards)
-                    ].spacing(20),
-                    Container::new(
-                        scrollable(card_rows).height(Length::Fill).width(400)
-                    ).height(Length::Fill).width(Length::Fill).align_x(Horizontal::Center),
-                ].spacing(20).align_items(Alignment::Center)
-            ).padding(10).into()
-        }
+    container(
+        row![
+            column![
+                button("Экспорт .agrg").on_press(AgrgMsg::ExportCards),
+                button("Импорт .agrg").on_press(AgrgMsg::ImportCards),
+                button("Экспорт CSV").on_press(AgrgMsg::ExportCardTable(TableFormat::Csv)),
+                button("Экспорт JSON").on_press(AgrgMsg::ExportCardTable(TableFormat::Json)),
+                // CSV or JSON, by extension
+                button("Импорт с заменой").on_press(AgrgMsg::ImportCardTable(ImportMode::Replace)),
+                button("Импорт в свободные").on_press(AgrgMsg::ImportCardTable(ImportMode::Merge)),
+            ].spacing(20),
+            Container::new(
+                scrollable(card_rows).height(Length::Fill).width(880)
+            ).height(Length::Fill).width(Length::Fill).align_x(Horizontal::Center),
+        ].spacing(20).align_items(Alignment::Center)
+    ).padding(10).into()
+}
+
+// name and note of a slot, read-only until the device is known
+fn holder_inputs(slot: usize, roster: Option<&Roster>) -> iced::Element<'static, AgrgMsg> {
+    let holder = roster.and_then(|roster| roster.holder(slot)).cloned().unwrap_or_default();
+    let mut name = text_input("", &holder.name).width(200);
+    let mut note = text_input("", &holder.note).width(200);
+
+    if roster.is_some() {
+        let other = holder.clone();
+        name = name.on_input(move |name| AgrgMsg::HolderEdited(slot, Holder { name, ..other.clone() }));
+        note = note.on_input(move |note| AgrgMsg::HolderEdited(slot, Holder { note, ..holder.clone() }));
     }
+
+    row![name, note].spacing(20).into()
+}
+
+// journal filter dates are typed as in the journal table
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d").ok()
+}
+
+// serial ports plus the built-in demo device
+fn available_ports() -> Vec<String> {
+    let mut ports = utils::get_available_ports().unwrap_or_default();
+    ports.push(utils::sim::DEMO_PORT.to_string());
+    ports
 }
 
 fn sanitize_hex_input(input: &str, max_length: usize) -> String {
@@ -604,85 +1256,95 @@ fn sanitize_pin(input: &str, max_length: usize) -> String {
 }
 
 
-fn settings(data: Vec<u8>, option_map: &Vec<Vec<String>>, time: String, custom_data: Option<String>, admin_passwd: String, logo: iced::widget::image::Handle) -> iced::Element<'static, AgrgMsg> {
-    match data.as_slice() {
-        [] => column![
-            Text::new("No Data loaded").height(Length::Fill),
-            button("Import").on_press(AgrgMsg::ImportSettings)
-        ].into(),
-        _ => {    
-
-            let placeholder = custom_data.unwrap_or("".to_string());
-            let mut row = Column::new();
-            let headers = ["Режим работы", "Формат кодонаборной панели", "Формат считывателя", "Режим доступа"];
-
-            // pick list for each byte
-            for (index, &byte) in data[0..4].iter().enumerate() {
-                let selected = option_map[index][byte as usize].clone();
-                let on_select = move |new_selection: String| AgrgMsg::SettingsUpdate(index, new_selection);
-                let pick_list = pick_list(
-                    option_map[index].clone(),
-                    Some(selected),
-                    on_select
-                );
-                row = row.push(column![headers[index], pick_list]);
-            }
+// a byte past ALL shows as unknown until picked over
+fn option_pick<T>(label: &'static str, index: usize, all: &'static [T], value: Option<u8>) -> iced::Element<'static, AgrgMsg>
+where
+    T: Copy + PartialEq + std::fmt::Display + 'static,
+{
+    let selected = value.and_then(|value| option_from_byte(all, index, value).ok());
+    let placeholder = match value {
+        Some(value) if selected.is_none() => format!("Неизвестно (0x{:02X})", value),
+        _ => String::new(),
+    };
+
+    column![
+        label,
+        pick_list(all, selected, move |option| AgrgMsg::SettingChanged(index, option_to_byte(all, &option)))
+            .placeholder(placeholder)
+    ].into()
+}
 
+// `idle`: the port is free for the buttons that talk to the handle right away
+fn settings(data: &MemoryImage, time: String, custom_data: Option<String>, admin_passwd: String, logo: iced::widget::image::Handle, idle: bool) -> iced::Element<'static, AgrgMsg> {
+    let placeholder = custom_data.unwrap_or("".to_string());
+    let [work_mode, pinpad, reader, access] = FIELD_NAMES;
+
+    // pick list for each option byte, a bad byte only takes its own field out
+    let row: iced::Element<'static, AgrgMsg> = column![
+        option_pick(work_mode, 0, &WorkMode::ALL, data.setting(0)),
+        option_pick(pinpad, 1, &PinpadFormat::ALL, data.setting(1)),
+        option_pick(reader, 2, &ReaderFormat::ALL, data.setting(2)),
+        option_pick(access, 3, &AccessMode::ALL, data.setting(3)),
+    ].spacing(10).into();
+
+    column![
+        // import export
+        row![
+            button("Импорт").on_press(AgrgMsg::ImportSettings),
+            button("Экспорт").on_press(AgrgMsg::ExportSettings)
+        ].spacing(20),
+        Space::new(0, 20),
+        
+        // MAIN BODY
+        row![
+            Image::new(logo).height(200).width(200),
             column![
-                // import export
-                row![
-                    button("Импорт").on_press(AgrgMsg::ImportSettings),
-                    button("Экспорт").on_press(AgrgMsg::ExportSettings)
-                ].spacing(20),
-                Space::new(0, 20),
-                
-                // MAIN BODY
                 row![
-                    Image::new(logo).height(200).width(200),
-                    column![
-                        row![
-                           
+                   
     
-                            row.spacing(10),
-                            Space::new(20, 0),
+                    row,
+                    Space::new(20, 0),
+                    column![
+                        Text::new("PIN Администратора"),
+                        text_input(&admin_passwd, &admin_passwd)
+                            .on_input(move |v| {
+                                let cleaned = sanitize_admin_passwd(&v, 6);
+                                AgrgMsg::AdminPasswdEdited(cleaned)
+                            })
+                            .width(120)
+                            .padding(5),
+                        match data.admin_pin().iter().find(|&&digit| digit > 9) {
+                            Some(&digit) => Text::new(format!("Неизвестно (0x{:02X}), введите PIN заново", digit))
+                                .style(Color::from_rgb(1.0, 0.3, 0.3)).into(),
+                            None => iced::Element::from(Space::new(0, 0)),
+                        },
+                        // TIME CONTAINER
+                        container(
                             column![
-                                Text::new("PIN Администратора"),
-                                text_input(&admin_passwd, &admin_passwd)
-                                    .on_input(move |v| {
-                                        let cleaned = sanitize_admin_passwd(&v, 6);
-                                        AgrgMsg::AdminPasswdEdited(cleaned)
-                                    })
-                                    .width(120)
-                                    .padding(5),
-                                // TIME CONTAINER
-                                container(
-                                    column![
     
-                                        row![
-                                            Text::new(time),
-                                            button("Sync").on_press(AgrgMsg::TimeSync)
-                                        ].spacing(20)
-                                    ]
-                                ),
-                                Space::new(0, 20),
-                            ].spacing(10),
-                        ].spacing(20),
-                        Space::new(0, 30),
-                        // custom data input field with a save button
-                        row![
-                            text_input(&placeholder, &placeholder)
-                                .on_input(move |v| {
-                                    AgrgMsg::CustomDataChange(v)
-                                })
-                                .width(500),
-                            button("Сохранить").on_press(AgrgMsg::SaveCustomData)
-                        ],
-                    ]
-                ]
-                
-            ].width(Length::Fill).align_items(Alignment::Center).into()
-        }
-    }
+                                row![
+                                    Text::new(time),
+                                    button("Sync").on_press_maybe(if idle { Some(AgrgMsg::TimeSync) } else { None })
+                                ].spacing(20)
+                            ]
+                        ),
+                        Space::new(0, 20),
+                    ].spacing(10),
+                ].spacing(20),
+                Space::new(0, 30),
+                // custom data input field with a save button
+                row![
+                    text_input(&placeholder, &placeholder)
+                        .on_input(move |v| {
+                            AgrgMsg::CustomDataChange(v)
+                        })
+                        .width(500),
+                    button("Сохранить").on_press_maybe(if idle { Some(AgrgMsg::SaveCustomData) } else { None })
+                ],
+            ]
+        ]
+        
+    ].width(Length::Fill).align_items(Alignment::Center).into()
 }
 
 
@@ -702,7 +1364,7 @@ fn settings(data: Vec<u8>, option_map: &Vec<Vec<String>>, time: String, custom_d
 //             let headers = ["Working mode", "Pinpad mode", "Card reader mode", "Access mode"];
 
 //             // pick list for each byte
-//             for (index, &byte) in data[0..4].iter().enumerate() {
+//             for (index, &byte) in data.settings()[0..4].iter().enumerate() {
 //                 // currently selected option
 //                 let selected = option_map[index][byte as usize].clone();
 
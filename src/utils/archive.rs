@@ -0,0 +1,254 @@
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    container,
+    error::Result,
+    journal::{JournalEntry, JournalEvent, TIME_FORMAT},
+};
+
+/// Every journal entry ever read from one device, kept on disk.
+///
+/// The archive is an append-only JSON Lines file per device, one entry per
+/// line, so the history survives the handle's ring wrapping around.
+#[derive(Debug, Clone)]
+pub struct JournalArchive {
+    path: PathBuf,
+    // oldest first
+    entries: Vec<JournalEntry>,
+    // time and event of every archived entry, the raw bytes don't take part
+    seen: HashSet<(NaiveDateTime, JournalEvent)>,
+}
+
+// one line of the archive file, payload fields only for the events that have one
+#[derive(Serialize, Deserialize)]
+struct Record {
+    time: String,
+    code: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    slot: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pin: Option<String>,
+    // hex, missing in archives written before it was kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw: Option<String>,
+}
+
+impl From<&JournalEntry> for Record {
+    fn from(entry: &JournalEntry) -> Self {
+        let mut record = Record {
+            time: entry.time.format(TIME_FORMAT).to_string(),
+            code: entry.event.code(),
+            slot: None,
+            uid: None,
+            pin: None,
+            raw: entry.raw.map(hex::encode),
+        };
+        match &entry.event {
+            JournalEvent::UserAccess { slot } => record.slot = Some(*slot),
+            JournalEvent::UnknownUid { uid } => record.uid = Some(uid.clone()),
+            JournalEvent::UnknownPin { pin } => record.pin = Some(pin.clone()),
+            _ => {},
+        }
+        record
+    }
+}
+
+impl Record {
+    fn into_entry(self) -> Option<JournalEntry> {
+        let time = NaiveDateTime::parse_from_str(&self.time, TIME_FORMAT).ok()?;
+        let event = match self.code {
+            0x01 => JournalEvent::UserAccess { slot: self.slot? },
+            0x02 => JournalEvent::UnknownUid { uid: self.uid? },
+            0x03 => JournalEvent::UnknownPin { pin: self.pin? },
+            code => JournalEvent::decode(code, 0xFF, &[]),
+        };
+        // a raw field that doesn't decode is dropped, the entry itself is still good
+        let raw = self.raw.and_then(|raw| hex::decode(raw).ok()).and_then(|raw| raw.try_into().ok());
+        Some(JournalEntry { time, event, raw })
+    }
+}
+
+impl JournalArchive {
+    /// Opens the archive of the device with this info string, empty if there is none yet.
+    pub fn open(device: &str) -> Result<Self> {
+        Self::open_at(archive_dir().join(format!("{}.jsonl", file_key(device))))
+    }
+
+    pub fn open_at(path: PathBuf) -> Result<Self> {
+        let mut archive = Self { path, entries: Vec::new(), seen: HashSet::new() };
+
+        let text = match fs::read_to_string(&archive.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(archive),
+            Err(e) => return Err(e.into()),
+        };
+
+        for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            // a line cut short by a crash should not lock the whole history away
+            match serde_json::from_str::<Record>(line).ok().and_then(Record::into_entry) {
+                Some(entry) => archive.insert(entry),
+                None => eprintln!("{}:{}: skipping malformed archive line", archive.path.display(), number + 1),
+            }
+        }
+        archive.entries.sort_by_key(|entry| entry.time);
+
+        Ok(archive)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Archived entries, oldest first.
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Appends the entries not archived yet, returns how many were new.
+    ///
+    /// Entries are the same if time, event and payload match.
+    pub fn merge(&mut self, entries: &[JournalEntry]) -> Result<usize> {
+        let mut lines = String::new();
+        let mut added = 0;
+
+        for entry in entries {
+            if self.seen.contains(&(entry.time, entry.event.clone())) {
+                continue;
+            }
+            lines.push_str(&serde_json::to_string(&Record::from(entry)).map_err(std::io::Error::from)?);
+            lines.push('\n');
+            self.insert(entry.clone());
+            added += 1;
+        }
+        if added == 0 {
+            return Ok(0);
+        }
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new().create(true).append(true).open(&self.path)?
+            .write_all(lines.as_bytes())?;
+        self.entries.sort_by_key(|entry| entry.time);
+
+        Ok(added)
+    }
+
+    fn insert(&mut self, entry: JournalEntry) {
+        if self.seen.insert((entry.time, entry.event.clone())) {
+            self.entries.push(entry);
+        }
+    }
+}
+
+/// Where archives and rosters are kept: `AGRG_ARCHIVE_DIR` if set, otherwise the user data directory.
+pub fn archive_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("AGRG_ARCHIVE_DIR") {
+        return PathBuf::from(dir);
+    }
+
+    let data = std::env::var_os("APPDATA")
+        .or_else(|| std::env::var_os("XDG_DATA_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_default();
+    data.join("agrg-sh-d-util").join("journal")
+}
+
+// longer keys are cut and get a hash of the whole info string, file names stop at 255 bytes
+const MAX_KEY_LEN: usize = 64;
+
+// the info string spans several lines, keep it readable as a file name
+pub(super) fn file_key(device: &str) -> String {
+    let key = device.split(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+    match key.len() {
+        0 => "unknown".into(),
+        len if len <= MAX_KEY_LEN => key,
+        // the key is ASCII, any byte index is a char boundary
+        _ => format!("{}_{:08x}", &key[..MAX_KEY_LEN - 9], container::crc32(device.as_bytes())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // unique per test so parallel runs don't share a file
+    fn scratch(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("agrg-archive-{}-{}.jsonl", std::process::id(), name));
+        _ = fs::remove_file(&path);
+        path
+    }
+
+    fn entry(minute: u32, event: JournalEvent) -> JournalEntry {
+        let time = chrono::NaiveDate::from_ymd_opt(2025, 5, 1).unwrap().and_hms_opt(10, minute, 0).unwrap();
+        JournalEntry { time, event, raw: None }
+    }
+
+    #[test]
+    fn merge_skips_archived_entries() {
+        let path = scratch("merge");
+        let first = [entry(0, JournalEvent::PowerOn), entry(1, JournalEvent::UserAccess { slot: 3 })];
+
+        let mut archive = JournalArchive::open_at(path.clone()).unwrap();
+        assert_eq!(archive.merge(&first).unwrap(), 2);
+        assert_eq!(archive.merge(&first).unwrap(), 0);
+
+        // a later dump overlaps the previous one
+        let second = [entry(1, JournalEvent::UserAccess { slot: 3 }), entry(2, JournalEvent::UnknownUid { uid: "deadbeef".into() })];
+        assert_eq!(archive.merge(&second).unwrap(), 1);
+
+        let reopened = JournalArchive::open_at(path.clone()).unwrap();
+        assert_eq!(reopened.entries(), archive.entries());
+        assert_eq!(reopened.entries().len(), 3);
+
+        _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn same_time_different_event_is_kept() {
+        let path = scratch("same-time");
+        let mut archive = JournalArchive::open_at(path.clone()).unwrap();
+
+        let added = archive.merge(&[entry(0, JournalEvent::UserAccess { slot: 1 }), entry(0, JournalEvent::UserAccess { slot: 2 })]);
+        assert_eq!(added.unwrap(), 2);
+
+        _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let path = scratch("malformed");
+        let mut archive = JournalArchive::open_at(path.clone()).unwrap();
+        archive.merge(&[entry(0, JournalEvent::PowerOn)]).unwrap();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"time\": \"cut sh").unwrap();
+
+        let reopened = JournalArchive::open_at(path.clone()).unwrap();
+        assert_eq!(reopened.entries().len(), 1);
+
+        _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn file_keys_stay_short() {
+        assert_eq!(file_key("AGRG SH-D\nSIM v1.0\nSN 00000001"), "AGRG_SH-D_SIM_v1.0_SN_00000001");
+        assert_eq!(file_key(" \n"), "unknown");
+
+        let long = |serial: &str| file_key(&format!("{}\nSN {}", "AGRG SH-D build ".repeat(20), serial));
+        assert_eq!(long("1").len(), MAX_KEY_LEN);
+        assert_ne!(long("1"), long("2"));
+    }
+}
@@ -0,0 +1,231 @@
+use chrono::{DateTime, Local, NaiveDateTime};
+use rfd::FileDialog;
+
+use super::{
+    agrg_text_info, cards,
+    container::{Container, ContainerError, PayloadKind},
+    diff,
+    error::{AgrgError, Result},
+    get_datetime,
+    image::{MemoryImage, IMAGE_SIZE},
+    mem_dump,
+    protocol::{self, CLOCK_SIZE, TEXT_SIZE},
+    read_text,
+    transfer::{Progress, RetryPolicy, Transfer},
+    transport::Transport,
+};
+
+// memory image | description, 0xFF padded | clock, BCD
+const PAYLOAD_SIZE: usize = IMAGE_SIZE + TEXT_SIZE + CLOCK_SIZE;
+
+/// Whole device state in one `.agrg` file.
+///
+/// The info string goes into the container header, the rest into the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    pub device: Option<String>,
+    pub created: DateTime<Local>,
+    pub image: MemoryImage,
+    pub text: String,
+    /// Clock as the device reported it, all 0xFF if it could not be read.
+    pub clock: [u8; CLOCK_SIZE],
+}
+
+impl Backup {
+    /// Reads info, description, clock and the whole memory.
+    ///
+    /// `dump` and `on_block` work like in `mem_dump`.
+    pub fn read(
+        transport: &mut dyn Transport,
+        policy: &RetryPolicy,
+        dump: &mut Transfer,
+        on_block: &mut dyn FnMut(Progress) -> bool,
+    ) -> Result<Self> {
+        let device = agrg_text_info(transport);
+        let text = read_text(transport)?;
+        // a handle with an unset clock is still worth backing up
+        let clock = match get_datetime(transport) {
+            Ok(raw) => raw.try_into().unwrap_or([0xFF; CLOCK_SIZE]),
+            Err(e) => {
+                eprintln!("backup without clock: {}", e);
+                [0xFF; CLOCK_SIZE]
+            },
+        };
+
+        mem_dump(transport, policy, dump, on_block)?;
+        let image = MemoryImage::from_dump(dump.data.clone())?;
+
+        Ok(Self { device, created: Local::now(), image, text, clock })
+    }
+
+    /// Clock reading, `None` if it was not set.
+    pub fn time(&self) -> Option<NaiveDateTime> {
+        protocol::bcd_to_datetime(&self.clock)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = self.image.as_bytes().to_vec();
+        payload.resize(IMAGE_SIZE, 0xFF);
+        let mut text = self.text.as_bytes().to_vec();
+        text.resize(TEXT_SIZE, 0xFF);
+        payload.extend(text);
+        payload.extend(self.clock);
+
+        Container { kind: PayloadKind::Backup, device: self.device.clone(), created: self.created, payload }.to_bytes()
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let container = Container::parse(bytes)?.ok_or(ContainerError::NotContainer)?;
+        if container.kind != PayloadKind::Backup {
+            return Err(ContainerError::WrongPayload { expected: PayloadKind::Backup, got: container.kind }.into());
+        }
+        if container.payload.len() != PAYLOAD_SIZE {
+            return Err(AgrgError::WrongSize { what: "backup", expected: PAYLOAD_SIZE, got: container.payload.len() });
+        }
+
+        let (image, rest) = container.payload.split_at(IMAGE_SIZE);
+        let (text, clock) = rest.split_at(TEXT_SIZE);
+        // same as read_text does with the reply
+        let text = cards::trim_empty(text.to_vec()).into_iter()
+            .filter(|&b| b <= 127)
+            .map(|b| b as char)
+            .collect();
+        let mut raw_clock = [0u8; CLOCK_SIZE];
+        raw_clock.copy_from_slice(clock);
+
+        Ok(Self {
+            device: container.device,
+            created: container.created,
+            image: MemoryImage::from_dump(image.to_vec())?,
+            text,
+            clock: raw_clock,
+        })
+    }
+
+    /// What a restore would change on a device holding `current` settings and cards and `text`.
+    ///
+    /// Without `current` every settings and cards block gets written.
+    pub fn changes(&self, current: Option<&[u8]>, text: &str) -> Vec<String> {
+        let mut changes: Vec<String> = match current {
+            Some(current) => diff::changes(current, self.image.upload_region()).iter().map(|c| c.to_string()).collect(),
+            None => vec!["Полная запись параметров и пользователей: данные с ручки не выгружались".into()],
+        };
+        if self.text != text {
+            changes.push(format!("Описание: \"{}\" -> \"{}\"", text, self.text));
+        }
+        changes
+    }
+}
+
+pub fn export_bin(backup: &Backup) -> Result<()> {
+    let timestamp = backup.created.format("%Y-%m-%d_%H-%M-%S");
+
+    let file_path = FileDialog::new()
+        .set_title("Сохранить резервную копию")
+        .set_file_name(format!("backup_{}_{}.agrg", backup.text, timestamp))
+        .save_file();
+
+    if let Some(path) = file_path {
+        std::fs::write(path, backup.to_bytes())?;
+    }
+    Ok(())
+}
+
+/// Backup from a file picked by the user, `None` if the dialog was closed.
+pub fn import_bin() -> Result<Option<Backup>> {
+    let file_path = FileDialog::new()
+        .set_title("Восстановить из резервной копии")
+        .pick_file();
+
+    match file_path {
+        Some(path) => Ok(Some(Backup::parse(&std::fs::read(path)?)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn sample() -> Backup {
+        // a backup always holds a full dump
+        let mut image = MemoryImage::from_dump(vec![0xFF; IMAGE_SIZE]).unwrap();
+        image.set_settings(&[1, 0, 2, 3, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6]).unwrap();
+        image.set_card_rfid(3, "04a1b2c3").unwrap();
+        let time = NaiveDateTime::parse_from_str("2025-05-01 10:20:30", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        Backup {
+            device: Some("AGRG SH-D\nSN 1".into()),
+            created: Local.timestamp_opt(1_746_000_000, 0).unwrap(),
+            image,
+            text: "gate 1".into(),
+            clock: protocol::datetime_to_bcd(time),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let backup = sample();
+        let parsed = Backup::parse(&backup.to_bytes()).unwrap();
+
+        assert_eq!(parsed, backup);
+        assert_eq!(parsed.time().unwrap().to_string(), "2025-05-01 10:20:30");
+    }
+
+    #[test]
+    fn unset_clock_has_no_time() {
+        let backup = Backup { clock: [0xFF; CLOCK_SIZE], ..sample() };
+        assert_eq!(Backup::parse(&backup.to_bytes()).unwrap().time(), None);
+    }
+
+    #[test]
+    fn short_payload_is_rejected() {
+        let backup = sample();
+        let mut payload = Container::parse(&backup.to_bytes()).unwrap().unwrap().payload;
+        payload.truncate(IMAGE_SIZE);
+        let short = Container { kind: PayloadKind::Backup, device: None, created: backup.created, payload };
+
+        assert!(matches!(
+            Backup::parse(&short.to_bytes()),
+            Err(AgrgError::WrongSize { what: "backup", expected: PAYLOAD_SIZE, got: IMAGE_SIZE }),
+        ));
+
+        let bytes = backup.to_bytes();
+        assert!(matches!(Backup::parse(&bytes[..bytes.len() / 2]), Err(AgrgError::Container(ContainerError::Truncated))));
+    }
+
+    #[test]
+    fn other_payloads_are_rejected() {
+        let image = Container::new(PayloadKind::Image, None, sample().image.into_bytes());
+        assert!(matches!(
+            Backup::parse(&image.to_bytes()),
+            Err(AgrgError::Container(ContainerError::WrongPayload { expected: PayloadKind::Backup, got: PayloadKind::Image })),
+        ));
+
+        // a raw dump has no container to hold the description and clock
+        assert!(matches!(
+            Backup::parse(sample().image.as_bytes()),
+            Err(AgrgError::Container(ContainerError::NotContainer)),
+        ));
+    }
+
+    #[test]
+    fn changes_against_the_device() {
+        let backup = sample();
+        assert!(backup.changes(Some(backup.image.upload_region()), "gate 1").is_empty());
+
+        let changes = backup.changes(Some(MemoryImage::default().upload_region()), "");
+        assert_eq!(changes, [
+            "Режим работы: 0 -> 1",
+            "Формат считывателя: 0 -> 2",
+            "Режим доступа: 0 -> 3",
+            "PIN администратора",
+            "Пользователь 3: UID - -> 04a1b2c3, PIN - -> -",
+            "Описание: \"\" -> \"gate 1\"",
+        ]);
+
+        assert_eq!(backup.changes(None, "gate 1").len(), 1);
+    }
+}
@@ -1,9 +1,14 @@
-use std::error::Error;
 use serde::{Serialize, Deserialize};
 use chrono::Local;
-use std::fs;
+use std::{collections::HashSet, fmt, fs, io::Write, path::Path};
 use rfd::FileDialog;
 
+use super::{
+    container::{self, Container, PayloadKind},
+    error::{AgrgError, Result},
+    image::SLOT_COUNT,
+};
+
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Card {
@@ -12,7 +17,8 @@ pub struct Card {
 }
 
 
-pub fn export_bin(cards: Vec<u8>, uid: String) -> Result<(), Box<dyn Error>> {
+/// Saves the card slots as an `.agrg` container tagged with the device info string.
+pub fn export_bin(cards: Vec<u8>, uid: String, device: Option<String>) -> Result<()> {
 
     let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
     
@@ -22,14 +28,14 @@ pub fn export_bin(cards: Vec<u8>, uid: String) -> Result<(), Box<dyn Error>> {
         .save_file();
 
     if let Some(path) = file_path {
-        fs::write(path, cards)?;
+        fs::write(path, Container::new(PayloadKind::Cards, device, cards).to_bytes())?;
     }
 
     Ok(())
 }
 
 
-pub fn rfid_to_bytes(hex_str: String) -> Result<Vec<u8>, Box<dyn Error>> {
+pub fn rfid_to_bytes(hex_str: String) -> Result<Vec<u8>> {
     let mut buffer = [0xFF; 10];
 
     let hex_even = match hex_str.len()%2 {
@@ -37,36 +43,37 @@ pub fn rfid_to_bytes(hex_str: String) -> Result<Vec<u8>, Box<dyn Error>> {
         _ => format!("{}{}", hex_str, "f")
     };
 
-    let bytes = hex::decode(hex_even)?;
+    let bytes = hex::decode(hex_even)
+        .map_err(|e| AgrgError::InvalidCard(format!("Invalid RFID: {}", e)))?;
     
     if bytes.len() > 10 {
-        return Err(format!(
+        return Err(AgrgError::InvalidCard(format!(
             "RFID too long: {} bytes (max 10)",
             bytes.len()
-        ).into());
+        )));
     }
     
     buffer[..bytes.len()].copy_from_slice(&bytes);
     Ok(buffer.to_vec())
 }
 
-pub fn pin_to_bytes(pin_str: String) -> Result<Vec<u8>, Box<dyn Error>> {
+pub fn pin_to_bytes(pin_str: String) -> Result<Vec<u8>> {
     let mut buffer = [0xFF; 6];
-    let digits: Result<Vec<u8>, _> = pin_str.chars()
+    let digits: Result<Vec<u8>> = pin_str.chars()
         .map(|c| {
             c.to_digit(10)
                 .and_then(|d| u8::try_from(d).ok())
-                .ok_or_else(|| format!("Invalid PIN character: '{}'", c))
+                .ok_or_else(|| AgrgError::InvalidCard(format!("Invalid PIN character: '{}'", c)))
         })
         .collect();
 
     let digits = digits?;
     
     if digits.len() > 6 {
-        return Err(format!(
+        return Err(AgrgError::InvalidCard(format!(
             "PIN too long: {} digits (max 6)",
             digits.len()
-        ).into());
+        )));
     }
     
     buffer[..digits.len()].copy_from_slice(&digits);
@@ -74,9 +81,9 @@ pub fn pin_to_bytes(pin_str: String) -> Result<Vec<u8>, Box<dyn Error>> {
 }
 
 
-pub fn parse(card_bytes: Vec<u8>) -> Result<Card, Box<dyn Error>> {
+pub fn parse(card_bytes: Vec<u8>) -> Result<Card> {
     if card_bytes.len() != 16 {
-        return Err(format!("incorrect entry length: must be 16, got {}", card_bytes.len()).into());
+        return Err(AgrgError::WrongSize { what: "entry", expected: 16, got: card_bytes.len() });
     }
 
     // trim trailing FF
@@ -90,11 +97,11 @@ pub fn parse(card_bytes: Vec<u8>) -> Result<Card, Box<dyn Error>> {
     let pin = trimmed_pin.iter()
         .map(|&b| {
             if b > 9 {
-                return Err(format!("Invalid PIN byte: {} (must be 0-9)", b));
+                return Err(AgrgError::InvalidCard(format!("Invalid PIN byte: {} (must be 0-9)", b)));
             }
             Ok((b'0' + b) as char)
         })
-        .collect::<Result<String, _>>()?;
+        .collect::<Result<String>>()?;
 
     Ok(Card { rfid, pin })
 }
@@ -109,16 +116,268 @@ pub fn trim_empty(data: Vec<u8>) -> Vec<u8> {
     data[..end].to_vec()
 }
 
-pub fn import_bin() -> Result<Vec<u8>, std::io::Error> {
+/// Card slots from a file picked by the user, `None` if the dialog was closed.
+///
+/// Raw files from before the container are read as they are.
+pub fn import_bin() -> Result<Option<Vec<u8>>> {
     let file_path = FileDialog::new()
         .set_title("Импортировать данные пользователей").pick_file();
 
+    match file_path {
+        Some(path) => Ok(Some(container::open(fs::read(path)?, PayloadKind::Cards)?)),
+        None => Ok(None),
+    }
+
+
+}
+
+/// One slot of a card table file, `slot,uid,pin,name` in CSV.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CardRow {
+    pub slot: usize,
+    #[serde(default)]
+    pub uid: String,
+    #[serde(default)]
+    pub pin: String,
+    /// Card holder from the roster, not stored on the device.
+    #[serde(default)]
+    pub name: String,
+}
+
+impl CardRow {
+    pub fn card(&self) -> Card {
+        Card { rfid: self.uid.to_lowercase(), pin: self.pin.clone() }
+    }
+}
+
+/// Problem with one row of an imported card table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    /// CSV line or position in the JSON array, from 1.
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// File format of a card table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFormat {
+    Csv,
+    Json,
+}
+
+impl TableFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TableFormat::Csv => "csv",
+            TableFormat::Json => "json",
+        }
+    }
+
+    /// Format by file extension, CSV unless it is `.json`.
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => TableFormat::Json,
+            _ => TableFormat::Csv,
+        }
+    }
+}
+
+pub fn write_table(out: &mut impl Write, rows: &[CardRow], format: TableFormat) -> Result<()> {
+    match format {
+        TableFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for row in rows {
+                writer.serialize(row).map_err(std::io::Error::from)?;
+            }
+            writer.flush()?;
+        },
+        TableFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, rows).map_err(std::io::Error::from)?;
+            out.write_all(b"\n")?;
+        },
+    }
+    Ok(())
+}
+
+/// Parses and validates a card table, every bad row is reported.
+pub fn read_table(data: &[u8], format: TableFormat) -> Result<Vec<CardRow>> {
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+
+    match format {
+        TableFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(data);
+            let headers = reader.headers()
+                .map_err(|e| AgrgError::InvalidRows(vec![RowError { line: 1, reason: e.to_string() }]))?
+                .clone();
+
+            for record in reader.records() {
+                let parsed = record.and_then(|record| {
+                    let line = record.position().map_or(0, |p| line_at(data, p));
+                    record.deserialize::<CardRow>(Some(&headers)).map(|row| (line, row))
+                });
+                match parsed {
+                    Ok(row) => rows.push(row),
+                    Err(e) => errors.push(RowError {
+                        line: e.position().map_or(0, |p| line_at(data, p)),
+                        reason: csv_reason(&e),
+                    }),
+                }
+            }
+        },
+        TableFormat::Json => {
+            let values: Vec<serde_json::Value> = serde_json::from_slice(data)
+                .map_err(|e| AgrgError::InvalidRows(vec![RowError { line: e.line(), reason: e.to_string() }]))?;
+            for (index, value) in values.into_iter().enumerate() {
+                match serde_json::from_value::<CardRow>(value) {
+                    Ok(row) => rows.push((index + 1, row)),
+                    Err(e) => errors.push(RowError { line: index + 1, reason: e.to_string() }),
+                }
+            }
+        },
+    }
+
+    let mut slots = HashSet::new();
+    let mut uids = HashSet::new();
+    for (line, row) in &rows {
+        if let Err(reason) = validate(row, &mut slots, &mut uids) {
+            errors.push(RowError { line: *line, reason });
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(rows.into_iter().map(|(_, row)| row).collect()),
+        false => {
+            errors.sort_by_key(|e| e.line);
+            Err(AgrgError::InvalidRows(errors))
+        },
+    }
+}
+
+fn validate(row: &CardRow, slots: &mut HashSet<usize>, uids: &mut HashSet<String>) -> std::result::Result<(), String> {
+    if !(1..=SLOT_COUNT).contains(&row.slot) {
+        return Err(format!("slot must be 1..={}, got {}", SLOT_COUNT, row.slot));
+    }
+    if row.uid.is_empty() && row.pin.is_empty() {
+        return Err("neither UID nor PIN".into());
+    }
+    rfid_to_bytes(row.uid.clone()).map_err(|e| e.to_string())?;
+    pin_to_bytes(row.pin.clone()).map_err(|e| e.to_string())?;
+
+    if !slots.insert(row.slot) {
+        return Err(format!("slot {} listed twice", row.slot));
+    }
+    if !row.uid.is_empty() && !uids.insert(row.uid.to_lowercase()) {
+        return Err(format!("UID {} listed twice", row.uid));
+    }
+    Ok(())
+}
+
+// csv counts a record from the blank lines before it, skip them
+fn line_at(data: &[u8], position: &csv::Position) -> usize {
+    let start = (position.byte() as usize).min(data.len());
+    let before = data[..start].iter().filter(|&&b| b == b'\n').count();
+    let blank = data[start..].iter().take_while(|&&b| b == b'\r' || b == b'\n').filter(|&&b| b == b'\n').count();
+    before + blank + 1
+}
+
+// the csv error repeats the position we already report
+fn csv_reason(e: &csv::Error) -> String {
+    match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+        _ => e.to_string(),
+    }
+}
+
+pub fn export_table(rows: &[CardRow], format: TableFormat, uid: String) -> Result<()> {
+    let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
+
+    let file_path = FileDialog::new()
+        .set_title("Сохранить данные пользователей")
+        .set_file_name(format!("cards_{}_{}.{}", uid, timestamp, format.extension()))
+        .save_file();
+
     if let Some(path) = file_path {
-        fs::read(path)
+        let mut file = fs::File::create(path)?;
+        write_table(&mut file, rows, format)?;
     }
-    else {
-        Ok(vec![0xff; 4080])
+    Ok(())
+}
+
+/// Card table from a CSV or JSON file picked by the user, `None` if the dialog was closed.
+pub fn import_table() -> Result<Option<Vec<CardRow>>> {
+    let file_path = FileDialog::new()
+        .set_title("Импортировать данные пользователей")
+        .add_filter("CSV, JSON", &["csv", "json"])
+        .pick_file();
+
+    match file_path {
+        Some(path) => Ok(Some(read_table(&fs::read(&path)?, TableFormat::of(&path))?)),
+        None => Ok(None),
     }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-}
\ No newline at end of file
+    fn row_errors(data: &str, format: TableFormat) -> Vec<(usize, String)> {
+        match read_table(data.as_bytes(), format) {
+            Err(AgrgError::InvalidRows(rows)) => rows.into_iter().map(|row| (row.line, row.reason)).collect(),
+            other => panic!("expected row errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_a_valid_table() {
+        let rows = read_table(b"slot,uid,pin,name\n1,04A1B2,1234,Ann\n7,,5555,\n", TableFormat::Csv).unwrap();
+        assert_eq!(rows, [
+            CardRow { slot: 1, uid: "04A1B2".into(), pin: "1234".into(), name: "Ann".into() },
+            CardRow { slot: 7, uid: String::new(), pin: "5555".into(), name: String::new() },
+        ]);
+    }
+
+    #[test]
+    fn reports_every_bad_csv_row_by_line() {
+        let data = "slot,uid,pin,name\n\
+                    1,04A1B2,1234,Ann\n\
+                    0,0102,,Bob\n\
+                    \n\
+                    2,,,Eve\n\
+                    3,04a1b2,,Copy\n\
+                    x,0A,,\n";
+        let errors = row_errors(data, TableFormat::Csv);
+
+        let lines: Vec<usize> = errors.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [3, 5, 6, 7]);
+        assert!(errors[0].1.contains("slot must be"));
+        assert_eq!(errors[1].1, "neither UID nor PIN");
+        assert!(errors[2].1.contains("listed twice"));
+    }
+
+    #[test]
+    fn reports_bad_json_rows_by_position() {
+        let data = r#"[{"slot": 1, "uid": "0A"}, {"slot": 2, "uid": "ZZ"}, {"uid": "0B"}]"#;
+        let errors = row_errors(data, TableFormat::Json);
+
+        let lines: Vec<usize> = errors.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [2, 3]);
+        assert!(errors[0].1.contains("Invalid RFID"));
+    }
+
+    #[test]
+    fn table_round_trip() {
+        let rows = vec![CardRow { slot: 4, uid: "0A0B".into(), pin: "42".into(), name: "Ann, Bob".into() }];
+        for format in [TableFormat::Csv, TableFormat::Json] {
+            let mut out = Vec::new();
+            write_table(&mut out, &rows, format).unwrap();
+            assert_eq!(read_table(&out, format).unwrap(), rows);
+        }
+    }
+}
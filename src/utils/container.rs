@@ -0,0 +1,259 @@
+use std::fmt;
+
+use chrono::{DateTime, Local, TimeZone};
+
+use super::error::Result;
+
+// .agrg layout, integers little endian:
+//   magic "AGRG" | version u8 | payload kind u8 | created, unix seconds i64
+//   | device info len u16 | device info utf-8 | payload len u32 | payload
+//   | crc32 of everything before it
+//
+// files without the magic are the raw dumps older versions wrote
+pub const MAGIC: &[u8; 4] = b"AGRG";
+pub const VERSION: u8 = 1;
+
+/// What a container holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    /// The 16 bytes of `image::SETTINGS`.
+    Settings,
+    /// The 255 slots of `image::CARDS`.
+    Cards,
+    /// A whole `mem_dump`.
+    Image,
+    /// A `backup::Backup`.
+    Backup,
+}
+
+impl PayloadKind {
+    fn code(&self) -> u8 {
+        match self {
+            PayloadKind::Settings => 1,
+            PayloadKind::Cards => 2,
+            PayloadKind::Image => 3,
+            PayloadKind::Backup => 5,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(PayloadKind::Settings),
+            2 => Some(PayloadKind::Cards),
+            3 => Some(PayloadKind::Image),
+            // 4 was meant for a bare journal and is never written
+            5 => Some(PayloadKind::Backup),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PayloadKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadKind::Settings => write!(f, "settings"),
+            PayloadKind::Cards => write!(f, "cards"),
+            PayloadKind::Image => write!(f, "memory image"),
+            PayloadKind::Backup => write!(f, "device backup"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerError {
+    /// No magic where a container is required.
+    NotContainer,
+    /// Written by a newer version of the tool.
+    UnsupportedVersion(u8),
+    UnknownPayload(u8),
+    /// File ends before the header says it should.
+    Truncated,
+    BadCrc { stored: u32, computed: u32 },
+    /// A cards file opened as settings or the other way round.
+    WrongPayload { expected: PayloadKind, got: PayloadKind },
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContainerError::NotContainer => write!(f, "not an .agrg container"),
+            ContainerError::UnsupportedVersion(v) => write!(f, "unsupported file version {}", v),
+            ContainerError::UnknownPayload(code) => write!(f, "unknown payload type {}", code),
+            ContainerError::Truncated => write!(f, "file is truncated"),
+            ContainerError::BadCrc { stored, computed } =>
+                write!(f, "checksum mismatch: stored {:08X}, computed {:08X}", stored, computed),
+            ContainerError::WrongPayload { expected, got } => write!(f, "expected {}, file holds {}", expected, got),
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+/// Contents of an `.agrg` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    pub kind: PayloadKind,
+    /// Info string of the device the payload came from.
+    pub device: Option<String>,
+    pub created: DateTime<Local>,
+    pub payload: Vec<u8>,
+}
+
+impl Container {
+    /// Container made now.
+    pub fn new(kind: PayloadKind, device: Option<String>, payload: Vec<u8>) -> Self {
+        Self { kind, device, created: Local::now(), payload }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let device = self.device.as_deref().unwrap_or_default().as_bytes();
+        // both lengths are bounded by what the device and the memory map hold
+        let device = &device[..device.len().min(u16::MAX as usize)];
+
+        let mut out = Vec::with_capacity(24 + device.len() + self.payload.len());
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.push(self.kind.code());
+        out.extend_from_slice(&self.created.timestamp().to_le_bytes());
+        out.extend_from_slice(&(device.len() as u16).to_le_bytes());
+        out.extend_from_slice(device);
+        out.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.payload);
+        out.extend_from_slice(&crc32(&out).to_le_bytes());
+        out
+    }
+
+    /// Parses a container, `Ok(None)` for a legacy file without the magic.
+    pub fn parse(bytes: &[u8]) -> Result<Option<Self>> {
+        if !bytes.starts_with(MAGIC) {
+            return Ok(None);
+        }
+        let mut reader = Reader { bytes, pos: MAGIC.len() };
+
+        let version = reader.take(1)?[0];
+        if version != VERSION {
+            return Err(ContainerError::UnsupportedVersion(version).into());
+        }
+        let code = reader.take(1)?[0];
+        let kind = PayloadKind::from_code(code).ok_or(ContainerError::UnknownPayload(code))?;
+        let created = i64::from_le_bytes(reader.array()?);
+        let device_len = u16::from_le_bytes(reader.array()?) as usize;
+        let device = String::from_utf8_lossy(reader.take(device_len)?).into_owned();
+        let payload_len = u32::from_le_bytes(reader.array()?) as usize;
+        let payload = reader.take(payload_len)?.to_vec();
+
+        let body = reader.pos;
+        let stored = u32::from_le_bytes(reader.array()?);
+        let computed = crc32(&bytes[..body]);
+        if stored != computed {
+            return Err(ContainerError::BadCrc { stored, computed }.into());
+        }
+
+        Ok(Some(Self {
+            kind,
+            device: Some(device).filter(|d| !d.is_empty()),
+            created: Local.timestamp_opt(created, 0).single().unwrap_or_default(),
+            payload,
+        }))
+    }
+}
+
+/// Payload of a file that should hold `expected`, legacy raw files are passed through as they are.
+pub fn open(bytes: Vec<u8>, expected: PayloadKind) -> Result<Vec<u8>> {
+    match Container::parse(&bytes)? {
+        Some(container) if container.kind == expected => Ok(container.payload),
+        Some(container) => Err(ContainerError::WrongPayload { expected, got: container.kind }.into()),
+        None => Ok(bytes),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let chunk = self.bytes.get(self.pos..self.pos + len).ok_or(ContainerError::Truncated)?;
+        self.pos += len;
+        Ok(chunk)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+}
+
+// CRC-32/ISO-HDLC, the zip and png one
+pub(super) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::error::AgrgError;
+
+    fn sample() -> Container {
+        Container::new(PayloadKind::Cards, Some("AGRG SH-D\nSN 1".into()), vec![0x04, 0xA1, 0xFF, 0x00])
+    }
+
+    #[test]
+    fn crc_is_the_zip_one() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let container = sample();
+        let parsed = Container::parse(&container.to_bytes()).unwrap().unwrap();
+
+        assert_eq!(parsed.kind, container.kind);
+        assert_eq!(parsed.device, container.device);
+        assert_eq!(parsed.created.timestamp(), container.created.timestamp());
+        assert_eq!(parsed.payload, container.payload);
+    }
+
+    #[test]
+    fn legacy_files_pass_through() {
+        let raw = vec![0xFF; 0x0FF0];
+        assert!(Container::parse(&raw).unwrap().is_none());
+        assert_eq!(open(raw.clone(), PayloadKind::Cards).unwrap(), raw);
+    }
+
+    #[test]
+    fn damaged_files_are_rejected() {
+        let bytes = sample().to_bytes();
+
+        let mut flipped = bytes.clone();
+        flipped[bytes.len() - 6] ^= 0x01;
+        assert!(matches!(Container::parse(&flipped), Err(AgrgError::Container(ContainerError::BadCrc { .. }))));
+
+        for len in [5, 20, bytes.len() - 1] {
+            assert!(matches!(Container::parse(&bytes[..len]), Err(AgrgError::Container(ContainerError::Truncated))));
+        }
+
+        let mut newer = bytes.clone();
+        newer[4] = VERSION + 1;
+        assert!(matches!(Container::parse(&newer), Err(AgrgError::Container(ContainerError::UnsupportedVersion(2)))));
+    }
+
+    #[test]
+    fn payload_kind_is_checked() {
+        let bytes = sample().to_bytes();
+        assert_eq!(open(bytes.clone(), PayloadKind::Cards).unwrap(), sample().payload);
+        assert!(matches!(
+            open(bytes, PayloadKind::Settings),
+            Err(AgrgError::Container(ContainerError::WrongPayload { expected: PayloadKind::Settings, got: PayloadKind::Cards })),
+        ));
+    }
+}
@@ -0,0 +1,110 @@
+use super::{cards, settings::FIELD_NAMES, upload_blocks};
+
+/// One upload block that differs from the device snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Settings byte at `index` in 0x0000..0x0008.
+    Setting { index: usize, old: u8, new: u8 },
+    AdminPin,
+    /// Card slot, numbered like in the cards tab.
+    Card { slot: usize, old: cards::Card, new: cards::Card },
+    /// Block that does not parse as a card, shown by address.
+    Raw { addr: usize },
+}
+
+/// Upload blocks of `image` that differ from `snapshot`, or all of them without a snapshot.
+pub fn changed_blocks(snapshot: Option<&[u8]>, image: &[u8]) -> Vec<(usize, usize)> {
+    upload_blocks().into_iter()
+        .filter(|&(addr, len)| match snapshot {
+            Some(old) => old.get(addr..addr + len) != image.get(addr..addr + len),
+            None => true,
+        })
+        .collect()
+}
+
+/// Human-readable changes for the pre-upload preview.
+pub fn changes(snapshot: &[u8], image: &[u8]) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    for (addr, len) in changed_blocks(Some(snapshot), image) {
+        let (Some(old), Some(new)) = (snapshot.get(addr..addr + len), image.get(addr..addr + len)) else {
+            changes.push(Change::Raw { addr });
+            continue;
+        };
+
+        match addr {
+            0x0000 => changes.extend(
+                old.iter().zip(new).enumerate()
+                    .filter(|(_, (o, n))| o != n)
+                    .map(|(index, (&old, &new))| Change::Setting { index, old, new })
+            ),
+            0x000A => changes.push(Change::AdminPin),
+            _ => match (cards::parse(old.to_vec()), cards::parse(new.to_vec())) {
+                (Ok(old), Ok(new)) => changes.push(Change::Card { slot: addr / 16, old, new }),
+                _ => changes.push(Change::Raw { addr }),
+            },
+        }
+    }
+
+    changes
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Setting { index, old, new } => match FIELD_NAMES.get(*index) {
+                Some(name) => write!(f, "{}: {} -> {}", name, old, new),
+                None => write!(f, "Параметр {}: {} -> {}", index, old, new),
+            },
+            Change::AdminPin => write!(f, "PIN администратора"),
+            Change::Card { slot, old, new } =>
+                write!(f, "Пользователь {}: UID {} -> {}, PIN {} -> {}",
+                    slot, or_empty(&old.rfid), or_empty(&new.rfid), or_empty(&old.pin), or_empty(&new.pin)),
+            Change::Raw { addr } => write!(f, "Блок {:04X}", addr),
+        }
+    }
+}
+
+fn or_empty(value: &str) -> &str {
+    if value.is_empty() { "-" } else { value }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{cards::Card, image::MemoryImage};
+
+    #[test]
+    fn without_snapshot_everything_is_written() {
+        let image = MemoryImage::default();
+        assert_eq!(changed_blocks(None, image.upload_region()), upload_blocks());
+    }
+
+    #[test]
+    fn finds_changed_blocks() {
+        let old = MemoryImage::default();
+        let mut new = old.clone();
+        assert!(changed_blocks(Some(old.upload_region()), new.upload_region()).is_empty());
+
+        new.set_card(5, &Card { rfid: "0102".into(), pin: "42".into() }).unwrap();
+        new.set_setting(1, 0x02).unwrap();
+        assert_eq!(changed_blocks(Some(old.upload_region()), new.upload_region()), [(0x0000, 8), (0x0050, 16)]);
+
+        assert_eq!(changes(old.upload_region(), new.upload_region()), [
+            Change::Setting { index: 1, old: 0x00, new: 0x02 },
+            Change::Card {
+                slot: 5,
+                old: Card { rfid: String::new(), pin: String::new() },
+                new: Card { rfid: "0102".into(), pin: "42".into() },
+            },
+        ]);
+    }
+
+    #[test]
+    fn bytes_never_written_are_ignored() {
+        let old = MemoryImage::default();
+        let mut new = old.upload_region().to_vec();
+        new[0x08] = 0x55;
+        assert!(changed_blocks(Some(old.upload_region()), &new).is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+use std::{fmt, io};
+
+use super::{cards::RowError, container::ContainerError, protocol::ProtocolError, settings::SettingsError, transport::TransportError};
+
+/// Everything that can go wrong in `utils`, from the serial line up to imported files.
+#[derive(Debug)]
+pub enum AgrgError {
+    Io(io::Error),
+    Serial(serialport::Error),
+    Transport(TransportError),
+    Protocol(ProtocolError),
+    Settings(SettingsError),
+    /// `.agrg` file that is not a readable container.
+    Container(ContainerError),
+    /// UID or PIN that does not fit a card slot.
+    InvalidCard(String),
+    InvalidDatetime(String),
+    /// Device image, file or block of the wrong length.
+    WrongSize { what: &'static str, expected: usize, got: usize },
+    /// Slot or setting index past the memory map.
+    OutOfRange { what: &'static str, index: usize },
+    /// Every card slot holds a UID or PIN.
+    NoFreeSlot,
+    /// Imported card table with bad rows, all of them.
+    InvalidRows(Vec<RowError>),
+    /// Block that kept failing after every retry.
+    Block { addr: usize, attempts: u32, source: Box<AgrgError> },
+    /// The progress callback asked the transfer to stop.
+    Cancelled,
+}
+
+pub type Result<T> = std::result::Result<T, AgrgError>;
+
+impl fmt::Display for AgrgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgrgError::Io(e) => write!(f, "{}", e),
+            AgrgError::Serial(e) => write!(f, "{}", e),
+            AgrgError::Transport(e) => write!(f, "{}", e),
+            AgrgError::Protocol(e) => write!(f, "{}", e),
+            AgrgError::Settings(e) => write!(f, "{}", e),
+            AgrgError::Container(e) => write!(f, "{}", e),
+            AgrgError::InvalidCard(msg) => write!(f, "{}", msg),
+            AgrgError::InvalidDatetime(msg) => write!(f, "invalid datetime: {}", msg),
+            AgrgError::WrongSize { what, expected, got } =>
+                write!(f, "incorrect {} length: must be {}, got {}", what, expected, got),
+            AgrgError::OutOfRange { what, index } => write!(f, "{} out of range: {}", what, index),
+            AgrgError::NoFreeSlot => write!(f, "no free card slot left"),
+            AgrgError::InvalidRows(rows) => {
+                write!(f, "invalid rows: {}", rows.len())?;
+                rows.iter().try_for_each(|row| write!(f, "\n{}", row))
+            },
+            AgrgError::Block { addr, attempts, source } =>
+                write!(f, "block {:04X} failed after {} attempts: {}", addr, attempts, source),
+            AgrgError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for AgrgError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AgrgError::Io(e) => Some(e),
+            AgrgError::Serial(e) => Some(e),
+            AgrgError::Transport(e) => Some(e),
+            AgrgError::Protocol(e) => Some(e),
+            AgrgError::Settings(e) => Some(e),
+            AgrgError::Container(e) => Some(e),
+            AgrgError::Block { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AgrgError {
+    fn from(e: io::Error) -> Self {
+        AgrgError::Io(e)
+    }
+}
+
+impl From<serialport::Error> for AgrgError {
+    fn from(e: serialport::Error) -> Self {
+        AgrgError::Serial(e)
+    }
+}
+
+impl From<TransportError> for AgrgError {
+    fn from(e: TransportError) -> Self {
+        AgrgError::Transport(e)
+    }
+}
+
+impl From<ProtocolError> for AgrgError {
+    fn from(e: ProtocolError) -> Self {
+        AgrgError::Protocol(e)
+    }
+}
+
+impl From<SettingsError> for AgrgError {
+    fn from(e: SettingsError) -> Self {
+        AgrgError::Settings(e)
+    }
+}
+
+impl From<ContainerError> for AgrgError {
+    fn from(e: ContainerError) -> Self {
+        AgrgError::Container(e)
+    }
+}
+
+impl AgrgError {
+    /// True if the transfer stopped because the user asked it to.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, AgrgError::Cancelled)
+    }
+}
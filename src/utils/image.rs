@@ -0,0 +1,309 @@
+use std::ops::Range;
+
+use super::{
+    cards::{self, Card, CardRow},
+    error::{AgrgError, Result},
+    settings::{DeviceSettings, SettingsError},
+};
+
+// memory map, reference protocol documentation for details
+pub const SETTINGS: Range<usize> = 0x0000..0x0010;
+pub const ADMIN_PIN: Range<usize> = 0x000A..0x0010;
+pub const CARDS: Range<usize> = 0x0010..0x1000;
+pub const JOURNAL: Range<usize> = 0x1000..0x8000;
+
+pub const ENTRY_SIZE: usize = 16;
+pub const SLOT_COUNT: usize = 255;
+pub const IMAGE_SIZE: usize = 0x8000;
+
+/// Device memory as read by `mem_dump`, split into named regions.
+///
+/// Always covers settings and cards; the journal is whatever the dump
+/// returned past 0x1000 and may be empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryImage {
+    raw: Vec<u8>,
+}
+
+impl Default for MemoryImage {
+    /// Zeroed settings, empty card slots, no journal.
+    fn default() -> Self {
+        let mut raw = vec![0x00; SETTINGS.end];
+        raw.resize(CARDS.end, 0xFF);
+        Self { raw }
+    }
+}
+
+impl MemoryImage {
+    pub fn from_dump(raw: Vec<u8>) -> Result<Self> {
+        if raw.len() < CARDS.end {
+            return Err(AgrgError::WrongSize { what: "image", expected: CARDS.end, got: raw.len() });
+        }
+        if raw.len() > IMAGE_SIZE {
+            return Err(AgrgError::WrongSize { what: "image", expected: IMAGE_SIZE, got: raw.len() });
+        }
+        Ok(Self { raw })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.raw
+    }
+
+    /// Settings and cards, the part `mem_upload` writes.
+    pub fn upload_region(&self) -> &[u8] {
+        &self.raw[..CARDS.end]
+    }
+
+    pub fn settings(&self) -> &[u8] {
+        &self.raw[SETTINGS]
+    }
+
+    pub fn set_settings(&mut self, settings: &[u8]) -> Result<()> {
+        write_region(&mut self.raw[SETTINGS], "settings", settings)
+    }
+
+    pub fn set_device_settings(&mut self, settings: &DeviceSettings) {
+        self.raw[SETTINGS].copy_from_slice(&settings.to_bytes());
+    }
+
+    pub fn setting(&self, index: usize) -> Option<u8> {
+        self.settings().get(index).copied()
+    }
+
+    pub fn set_setting(&mut self, index: usize, value: u8) -> Result<()> {
+        let byte = self.raw[SETTINGS].get_mut(index)
+            .ok_or(AgrgError::OutOfRange { what: "setting index", index })?;
+        *byte = value;
+        Ok(())
+    }
+
+    /// Admin PIN, one digit per byte.
+    pub fn admin_pin(&self) -> &[u8] {
+        &self.raw[ADMIN_PIN]
+    }
+
+    pub fn set_admin_pin(&mut self, digits: &[u8]) -> Result<()> {
+        if let Some(&d) = digits.iter().find(|&&d| d > 9) {
+            return Err(SettingsError::InvalidPinDigit(d).into());
+        }
+        write_region(&mut self.raw[ADMIN_PIN], "admin PIN", digits)
+    }
+
+    /// All 255 card slots as stored on the device.
+    pub fn cards_raw(&self) -> &[u8] {
+        &self.raw[CARDS]
+    }
+
+    pub fn set_cards_raw(&mut self, cards: &[u8]) -> Result<()> {
+        write_region(&mut self.raw[CARDS], "cards", cards)
+    }
+
+    /// Raw 16-byte entry of a card slot, slots are numbered 1..=255.
+    pub fn card_raw(&self, slot: usize) -> Option<&[u8]> {
+        slot_range(slot).map(|range| &self.raw[range])
+    }
+
+    pub fn card(&self, slot: usize) -> Result<Card> {
+        let raw = self.card_raw(slot).ok_or(AgrgError::OutOfRange { what: "slot", index: slot })?;
+        cards::parse(raw.to_vec())
+    }
+
+    /// Every slot with its parsed card, in slot order.
+    pub fn cards(&self) -> impl Iterator<Item = (usize, Result<Card>)> + '_ {
+        (1..=SLOT_COUNT).map(|slot| (slot, self.card(slot)))
+    }
+
+    pub fn set_card(&mut self, slot: usize, card: &Card) -> Result<()> {
+        let mut entry = cards::rfid_to_bytes(card.rfid.clone())?;
+        entry.append(&mut cards::pin_to_bytes(card.pin.clone())?);
+        self.write_slot(slot, 0, &entry)
+    }
+
+    pub fn set_card_rfid(&mut self, slot: usize, rfid: &str) -> Result<()> {
+        let bytes = cards::rfid_to_bytes(rfid.to_string())?;
+        self.write_slot(slot, 0, &bytes)
+    }
+
+    pub fn set_card_pin(&mut self, slot: usize, pin: &str) -> Result<()> {
+        let bytes = cards::pin_to_bytes(pin.to_string())?;
+        self.write_slot(slot, 10, &bytes)
+    }
+
+    /// First slot with neither UID nor PIN.
+    pub fn free_slot(&self) -> Option<usize> {
+        (1..=SLOT_COUNT).find(|&slot| self.is_free(slot))
+    }
+
+    pub fn is_free(&self, slot: usize) -> bool {
+        self.card(slot).is_ok_and(|card| card.rfid.is_empty() && card.pin.is_empty())
+    }
+
+    /// Slot holding this UID, if any.
+    pub fn slot_of_uid(&self, uid: &str) -> Result<Option<usize>> {
+        let uid = cards::rfid_to_bytes(uid.to_string())?;
+        Ok((1..=SLOT_COUNT).find(|&slot| self.card_raw(slot).is_some_and(|raw| raw[..10] == uid[..])))
+    }
+
+    /// Puts the card into the first free slot and returns it, a UID is enrolled only once.
+    pub fn enroll(&mut self, card: &Card) -> Result<usize> {
+        if card.rfid.is_empty() {
+            return Err(AgrgError::InvalidCard("empty UID".into()));
+        }
+        if let Some(slot) = self.slot_of_uid(&card.rfid)? {
+            return Err(AgrgError::InvalidCard(format!("UID already enrolled in slot {}", slot)));
+        }
+
+        let slot = self.free_slot().ok_or(AgrgError::NoFreeSlot)?;
+        self.set_card(slot, card)?;
+        Ok(slot)
+    }
+
+    /// Loads a card table from a file, nothing changes if it does not fit.
+    pub fn import_cards(&mut self, rows: &[CardRow], mode: ImportMode) -> Result<Imported> {
+        let mut image = self.clone();
+        let mut imported = Imported::default();
+
+        if mode == ImportMode::Replace {
+            image.raw[CARDS].fill(0xFF);
+        }
+        for row in rows {
+            let slot = match mode {
+                ImportMode::Replace => row.slot,
+                ImportMode::Merge => {
+                    if !row.uid.is_empty() && image.slot_of_uid(&row.uid)?.is_some() {
+                        imported.skipped.push(row.clone());
+                        continue;
+                    }
+                    match image.is_free(row.slot) {
+                        true => row.slot,
+                        false => image.free_slot().ok_or(AgrgError::NoFreeSlot)?,
+                    }
+                },
+            };
+            image.set_card(slot, &row.card())?;
+            imported.placed.push((slot, row.clone()));
+        }
+
+        *self = image;
+        Ok(imported)
+    }
+
+    /// Journal region in memory order, may be empty.
+    pub fn journal(&self) -> &[u8] {
+        self.raw.get(JOURNAL.start..).unwrap_or_default()
+    }
+
+    fn write_slot(&mut self, slot: usize, offset: usize, bytes: &[u8]) -> Result<()> {
+        let range = slot_range(slot).ok_or(AgrgError::OutOfRange { what: "slot", index: slot })?;
+        let target = self.raw[range].get_mut(offset..offset + bytes.len())
+            .ok_or(AgrgError::WrongSize { what: "entry", expected: ENTRY_SIZE - offset, got: bytes.len() })?;
+        target.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// How an imported card table meets the cards already there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Clear the table, every row goes to its own slot.
+    Replace,
+    /// Keep the table, rows go to their slot if it is free or to the first free one,
+    /// UIDs already enrolled are skipped.
+    Merge,
+}
+
+/// Outcome of `import_cards`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Imported {
+    /// Rows with the slot they ended up in.
+    pub placed: Vec<(usize, CardRow)>,
+    pub skipped: Vec<CardRow>,
+}
+
+fn slot_range(slot: usize) -> Option<Range<usize>> {
+    match slot {
+        1..=SLOT_COUNT => Some(slot * ENTRY_SIZE..(slot + 1) * ENTRY_SIZE),
+        _ => None,
+    }
+}
+
+fn write_region(target: &mut [u8], what: &'static str, bytes: &[u8]) -> Result<()> {
+    if bytes.len() != target.len() {
+        return Err(AgrgError::WrongSize { what, expected: target.len(), got: bytes.len() });
+    }
+    target.copy_from_slice(bytes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(rfid: &str, pin: &str) -> Card {
+        Card { rfid: rfid.into(), pin: pin.into() }
+    }
+
+    fn row(slot: usize, uid: &str, pin: &str) -> CardRow {
+        CardRow { slot, uid: uid.into(), pin: pin.into(), name: String::new() }
+    }
+
+    fn uids(image: &MemoryImage) -> Vec<(usize, String)> {
+        image.cards()
+            .filter_map(|(slot, card)| card.ok().filter(|c| !c.rfid.is_empty()).map(|c| (slot, c.rfid)))
+            .collect()
+    }
+
+    #[test]
+    fn replace_clears_the_table() {
+        let mut image = MemoryImage::default();
+        image.set_card(1, &card("0a0a", "")).unwrap();
+
+        let imported = image.import_cards(&[row(5, "04a1", "1234")], ImportMode::Replace).unwrap();
+        assert_eq!(imported.placed, [(5, row(5, "04a1", "1234"))]);
+        assert_eq!(uids(&image), [(5, "04a1".to_string())]);
+    }
+
+    #[test]
+    fn merge_keeps_the_table() {
+        let mut image = MemoryImage::default();
+        image.set_card(1, &card("0a0a", "")).unwrap();
+
+        let rows = [row(1, "04a1", ""), row(2, "0a0a", ""), row(9, "0b0b", "")];
+        let imported = image.import_cards(&rows, ImportMode::Merge).unwrap();
+
+        // slot 1 is taken so its row moves to the first free one, 0a0a is already there
+        assert_eq!(imported.placed, [(2, rows[0].clone()), (9, rows[2].clone())]);
+        assert_eq!(imported.skipped, [rows[1].clone()]);
+        assert_eq!(uids(&image), [(1, "0a0a".to_string()), (2, "04a1".to_string()), (9, "0b0b".to_string())]);
+    }
+
+    #[test]
+    fn full_table_has_no_free_slot() {
+        let mut image = MemoryImage::default();
+        for slot in 1..=SLOT_COUNT {
+            image.set_card_pin(slot, &format!("{}", slot)).unwrap();
+        }
+        let before = image.clone();
+
+        assert_eq!(image.free_slot(), None);
+        assert!(matches!(image.enroll(&card("04a1", "")), Err(AgrgError::NoFreeSlot)));
+        assert!(matches!(image.import_cards(&[row(3, "04a1", "")], ImportMode::Merge), Err(AgrgError::NoFreeSlot)));
+        // a failed import leaves the table as it was
+        assert_eq!(image, before);
+    }
+
+    #[test]
+    fn uid_is_enrolled_once() {
+        let mut image = MemoryImage::default();
+        image.set_card(1, &card("", "1111")).unwrap();
+
+        assert_eq!(image.enroll(&card("04a1b2", "4321")).unwrap(), 2);
+        assert_eq!(image.slot_of_uid("04a1b2").unwrap(), Some(2));
+        assert!(matches!(image.enroll(&card("04a1b2", "")), Err(AgrgError::InvalidCard(_))));
+        assert_eq!(image.free_slot(), Some(3));
+    }
+}
@@ -1,67 +1,334 @@
 
-use chrono::Local;
-use std::{error::Error, io::Write};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use std::{fmt, io::Write};
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+use super::{
+    cards,
+    error::{AgrgError, Result},
+    image::{ENTRY_SIZE, JOURNAL},
+    protocol,
+    roster::Names,
+};
+
+/// What a journal entry records, payload decoded but not localized.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum JournalEvent {
+    PowerOn,
+    UserAccess { slot: u8 },
+    /// Hex UID as stored in card slots.
+    UnknownUid { uid: String },
+    UnknownPin { pin: String },
+    HandleReleased,
+    ForcedOpen,
+    HandleLocked,
+    ProgMode,
+    BadAdminPin,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct JournalEntry {
-    timestamp: String,
-    event_type: String,
-    user_id: u8,
-    data: Vec<u8>,
-}
-
-const EVENT_TYPES: [&str; 11] = [
-    "Подача питания", // 0x00
-    "Пользователь", // 0x01
-    "Незарегистрированный UID", // 0x02
-    "Незарегистрированный PIN", // 0x03
-    "Unknown", // 0x04
-    "Unknown", // 0x05
-    "Рукоятка замка освобождена", // 0x06
-    "Принудительное освобождение замка/Взлом", // 0x07
-    "Рукоятка замка зафиксирована", // 0x08
-    "Вход в режим программирования", // 0x09
-    "Неверный PIN администратора", // 0x0A
-];
-
-pub fn journal_entry_to_string(entry: JournalEntry) -> Option<(String, String)> {
-
-    let empty_entry = JournalEntry { 
-        timestamp: String::from("20FF-FF-FF FF:FF:FF"), 
-        event_type: String::from("Unknown"), 
-        user_id: 0xFF, 
-        // data: String::from("ffffffffffffff")
-        data: vec![0xFF; 7] 
-    };
-    
-    if entry == empty_entry {
-        return None
+    pub time: NaiveDateTime,
+    pub event: JournalEvent,
+    /// Entry as stored on the device, `None` if only the decoded fields were kept.
+    pub raw: Option<[u8; ENTRY_SIZE]>,
+}
+
+// how timestamps are written in the journal tab and exports
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+impl JournalEvent {
+    /// Event byte as stored on the device.
+    pub fn code(&self) -> u8 {
+        match self {
+            JournalEvent::PowerOn => 0x00,
+            JournalEvent::UserAccess { .. } => 0x01,
+            JournalEvent::UnknownUid { .. } => 0x02,
+            JournalEvent::UnknownPin { .. } => 0x03,
+            JournalEvent::HandleReleased => 0x06,
+            JournalEvent::ForcedOpen => 0x07,
+            JournalEvent::HandleLocked => 0x08,
+            JournalEvent::ProgMode => 0x09,
+            JournalEvent::BadAdminPin => 0x0A,
+            JournalEvent::Unknown(code) => *code,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.kind().name()
     }
 
-    // bytes to string
-    let bytestring: String = entry.data.iter().map(|b| format!("{:2X}", b)).collect();
+    pub fn kind(&self) -> EventKind {
+        match self {
+            JournalEvent::PowerOn => EventKind::PowerOn,
+            JournalEvent::UserAccess { .. } => EventKind::UserAccess,
+            JournalEvent::UnknownUid { .. } => EventKind::UnknownUid,
+            JournalEvent::UnknownPin { .. } => EventKind::UnknownPin,
+            JournalEvent::HandleReleased => EventKind::HandleReleased,
+            JournalEvent::ForcedOpen => EventKind::ForcedOpen,
+            JournalEvent::HandleLocked => EventKind::HandleLocked,
+            JournalEvent::ProgMode => EventKind::ProgMode,
+            JournalEvent::BadAdminPin => EventKind::BadAdminPin,
+            JournalEvent::Unknown(_) => EventKind::Unknown,
+        }
+    }
 
-    // info construction
-    let info = match entry.event_type.as_str() {
-        "Пользователь" => { format!("{}", entry.user_id) },
-        "Незарегистрированный UID" => { format!("{:X}{}", entry.user_id, &bytestring[..bytestring.len()-2]) },
-        "Незарегистрированный PIN" => { format!("{}{}", entry.user_id, &bytestring[..10]) },
+    // user byte and 7 data bytes, the payload of the first three events starts in the user byte
+    pub(super) fn decode(code: u8, user: u8, data: &[u8]) -> Self {
+        let mut payload = vec![user];
+        payload.extend_from_slice(data);
+        let payload = cards::trim_empty(payload);
 
-        _ => String::new(),
-    };
+        match code {
+            0x00 => JournalEvent::PowerOn,
+            0x01 => JournalEvent::UserAccess { slot: user },
+            0x02 => JournalEvent::UnknownUid { uid: hex::encode(payload) },
+            0x03 => JournalEvent::UnknownPin {
+                pin: payload.iter().filter(|&&d| d <= 9).map(|d| d.to_string()).collect(),
+            },
+            0x06 => JournalEvent::HandleReleased,
+            0x07 => JournalEvent::ForcedOpen,
+            0x08 => JournalEvent::HandleLocked,
+            0x09 => JournalEvent::ProgMode,
+            0x0A => JournalEvent::BadAdminPin,
+            other => JournalEvent::Unknown(other),
+        }
+    }
+}
+
+impl fmt::Display for JournalEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalEvent::UserAccess { slot } => write!(f, "{} {}", self.name(), slot),
+            JournalEvent::UnknownUid { uid } => write!(f, "{} {}", self.name(), uid.to_uppercase()),
+            JournalEvent::UnknownPin { pin } => write!(f, "{} {}", self.name(), pin),
+            JournalEvent::Unknown(code) => write!(f, "{} {:02X}", self.name(), code),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+/// Event type without its payload, for filtering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    PowerOn,
+    UserAccess,
+    UnknownUid,
+    UnknownPin,
+    HandleReleased,
+    ForcedOpen,
+    HandleLocked,
+    ProgMode,
+    BadAdminPin,
+    Unknown,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 10] = [
+        EventKind::PowerOn,
+        EventKind::UserAccess,
+        EventKind::UnknownUid,
+        EventKind::UnknownPin,
+        EventKind::HandleReleased,
+        EventKind::ForcedOpen,
+        EventKind::HandleLocked,
+        EventKind::ProgMode,
+        EventKind::BadAdminPin,
+        EventKind::Unknown,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::PowerOn => "Подача питания",
+            EventKind::UserAccess => "Пользователь",
+            EventKind::UnknownUid => "Незарегистрированный UID",
+            EventKind::UnknownPin => "Незарегистрированный PIN",
+            EventKind::HandleReleased => "Рукоятка замка освобождена",
+            EventKind::ForcedOpen => "Принудительное освобождение замка/Взлом",
+            EventKind::HandleLocked => "Рукоятка замка зафиксирована",
+            EventKind::ProgMode => "Вход в режим программирования",
+            EventKind::BadAdminPin => "Неверный PIN администратора",
+            EventKind::Unknown => "Unknown",
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Journal search, every set criterion must match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JournalFilter {
+    /// First day to include.
+    pub from: Option<NaiveDate>,
+    /// Last day to include.
+    pub to: Option<NaiveDate>,
+    pub kind: Option<EventKind>,
+    /// User slot of access events.
+    pub slot: Option<u8>,
+    /// Part of an unknown UID, any case, empty matches everything.
+    pub uid: String,
+}
+
+/// How many entries each criterion of a filter matches on its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FilterCounts {
+    pub dates: usize,
+    pub kind: usize,
+    pub slot: usize,
+    pub uid: usize,
+    /// Entries matching the whole filter.
+    pub all: usize,
+}
+
+impl JournalFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == JournalFilter::default()
+    }
+
+    pub fn matches(&self, entry: &JournalEntry) -> bool {
+        self.dates_match(entry) && self.kind_matches(entry) && self.slot_matches(entry) && self.uid_matches(entry)
+    }
+
+    /// Entries that match, in the given order.
+    pub fn apply(&self, entries: &[JournalEntry]) -> Vec<JournalEntry> {
+        entries.iter().filter(|e| self.matches(e)).cloned().collect()
+    }
+
+    pub fn counts(&self, entries: &[JournalEntry]) -> FilterCounts {
+        let count = |check: &dyn Fn(&JournalEntry) -> bool| entries.iter().filter(|e| check(e)).count();
+        FilterCounts {
+            dates: count(&|e| self.dates_match(e)),
+            kind: count(&|e| self.kind_matches(e)),
+            slot: count(&|e| self.slot_matches(e)),
+            uid: count(&|e| self.uid_matches(e)),
+            all: count(&|e| self.matches(e)),
+        }
+    }
+
+    fn dates_match(&self, entry: &JournalEntry) -> bool {
+        let day = entry.time.date();
+        self.from.is_none_or(|from| day >= from) && self.to.is_none_or(|to| day <= to)
+    }
+
+    fn kind_matches(&self, entry: &JournalEntry) -> bool {
+        self.kind.is_none_or(|kind| entry.event.kind() == kind)
+    }
+
+    fn slot_matches(&self, entry: &JournalEntry) -> bool {
+        match (self.slot, &entry.event) {
+            (None, _) => true,
+            (Some(wanted), JournalEvent::UserAccess { slot }) => wanted == *slot,
+            _ => false,
+        }
+    }
+
+    fn uid_matches(&self, entry: &JournalEntry) -> bool {
+        let wanted = self.uid.trim().to_lowercase();
+        match &entry.event {
+            _ if wanted.is_empty() => true,
+            JournalEvent::UnknownUid { uid } => uid.contains(&wanted),
+            _ => false,
+        }
+    }
+}
+
+/// Field separator of exported CSV files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Delimiter {
+    #[default]
+    Comma,
+    /// What Excel expects in locales with a decimal comma.
+    Semicolon,
+    Tab,
+}
 
+impl Delimiter {
+    pub const ALL: [Delimiter; 3] = [Delimiter::Comma, Delimiter::Semicolon, Delimiter::Tab];
+
+    pub fn byte(&self) -> u8 {
+        match self {
+            Delimiter::Comma => b',',
+            Delimiter::Semicolon => b';',
+            Delimiter::Tab => b'\t',
+        }
+    }
+}
 
-   let info = format!("{} {}", entry.event_type, info);
+impl fmt::Display for Delimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delimiter::Comma => write!(f, "Запятая"),
+            Delimiter::Semicolon => write!(f, "Точка с запятой"),
+            Delimiter::Tab => write!(f, "Табуляция"),
+        }
+    }
+}
 
-   Some((entry.timestamp, info))
+/// How `write_csv` lays out the file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: Delimiter,
+    /// Start with a UTF-8 byte order mark so Excel doesn't garble Cyrillic.
+    pub bom: bool,
 }
 
+pub const CSV_HEADER: [&str; 7] = ["Timestamp", "Code", "Event", "Slot", "Holder", "Payload", "Raw"];
+
+/// File format of a journal export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// Array of `ExportEntry` objects.
+    Json,
+    /// Printable report, one table per day.
+    Html,
+}
 
-pub fn serializer(entry_vec: Vec<Option<(String, String)>>) -> Result<(), Box<dyn Error>> {
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [ExportFormat::Csv, ExportFormat::Json, ExportFormat::Html];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension().to_uppercase())
+    }
+}
+
+/// Device and export details shown at the top of an HTML report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportInfo {
+    /// Info string as returned by `agrg_text_info`.
+    pub device: Option<String>,
+    pub description: Option<String>,
+    pub exported: NaiveDateTime,
+}
+
+impl ReportInfo {
+    /// Report for an export made right now.
+    pub fn now(device: Option<String>, description: Option<String>) -> Self {
+        Self { device, description, exported: Local::now().naive_local() }
+    }
+}
+
+pub fn serializer(entries: &[JournalEntry], names: &Names, format: ExportFormat, csv: CsvOptions, info: &ReportInfo) -> Result<()> {
 
     let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
     
-    let filename = format!("journal_{}.csv", timestamp);
+    let filename = format!("journal_{}.{}", timestamp, format.extension());
 
     let file_path = rfd::FileDialog::new()
         .set_title("Сохранение журнала событий")
@@ -70,64 +337,589 @@ pub fn serializer(entry_vec: Vec<Option<(String, String)>>) -> Result<(), Box<dy
 
     print!("{:?}", file_path);
 
-    match file_path {
-        Some(path) => {
-            let mut file = std::fs::File::create(path)
-                .expect("Failed to create file");
-
-            file.write(b"Timestamp, Data\n")?;
+    if let Some(path) = file_path {
+        let mut file = std::fs::File::create(path)?;
 
-            for entry in entry_vec {
-                match entry {
-                    Some(tuple) => {
-                        let data = format!("{},{}\n", tuple.0, tuple.1); 
-                        file.write(data.as_bytes())?;
-                    },
-                    None => {}
-                }
-            };
-        },
-        None => {}
+        write_export(&mut file, entries, names, format, csv, info)?;
     };
     Ok(())
-} 
+}
+
+/// Writes the entries in `format`, CSV options and report info are used only where they apply.
+pub fn write_export(out: &mut impl Write, entries: &[JournalEntry], names: &Names, format: ExportFormat, csv: CsvOptions, info: &ReportInfo) -> Result<()> {
+    match format {
+        ExportFormat::Csv => write_csv(out, entries, names, csv),
+        ExportFormat::Json => write_json(out, entries, names),
+        ExportFormat::Html => write_html(out, entries, names, info),
+    }
+}
+
+/// Writes the entries as CSV, one column per field of `CSV_HEADER`.
+///
+/// Slot is set for user accesses only, payload holds the UID or PIN of
+/// refused attempts and raw is empty for entries kept without their bytes.
+pub fn write_csv(out: &mut impl Write, entries: &[JournalEntry], names: &Names, options: CsvOptions) -> Result<()> {
+    if options.bom {
+        out.write_all("\u{FEFF}".as_bytes())?;
+    }
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(options.delimiter.byte())
+        .from_writer(out);
+    writer.write_record(CSV_HEADER).map_err(std::io::Error::from)?;
+
+    for entry in entries {
+        let slot = match entry.event {
+            JournalEvent::UserAccess { slot } => slot.to_string(),
+            _ => String::new(),
+        };
+        let payload = match &entry.event {
+            JournalEvent::UnknownUid { uid } => uid.to_uppercase(),
+            JournalEvent::UnknownPin { pin } => pin.clone(),
+            _ => String::new(),
+        };
+
+        writer.write_record([
+            entry.time.format(TIME_FORMAT).to_string(),
+            format!("{:02X}", entry.event.code()),
+            entry.event.name().to_string(),
+            slot,
+            names.holder(&entry.event).unwrap_or_default().to_string(),
+            payload,
+            entry.raw.map(hex::encode_upper).unwrap_or_default(),
+        ]).map_err(std::io::Error::from)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// One event of a JSON export, payload fields only where the event has them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExportEntry {
+    pub time: String,
+    /// Event byte in hex, as in CSV and the HTML report.
+    pub code: String,
+    pub event: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub holder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+impl ExportEntry {
+    pub fn new(entry: &JournalEntry, names: &Names) -> Self {
+        let (slot, uid, pin) = match &entry.event {
+            JournalEvent::UserAccess { slot } => (Some(*slot), None, None),
+            JournalEvent::UnknownUid { uid } => (None, Some(uid.to_uppercase()), None),
+            JournalEvent::UnknownPin { pin } => (None, None, Some(pin.clone())),
+            _ => (None, None, None),
+        };
+        Self {
+            time: entry.time.format(TIME_FORMAT).to_string(),
+            code: format!("{:02X}", entry.event.code()),
+            event: entry.event.name(),
+            slot,
+            holder: names.holder(&entry.event).map(str::to_string),
+            uid,
+            pin,
+            raw: entry.raw.map(hex::encode_upper),
+        }
+    }
+}
+
+/// Writes the entries as a pretty-printed JSON array of `ExportEntry`.
+pub fn write_json(out: &mut impl Write, entries: &[JournalEntry], names: &Names) -> Result<()> {
+    let entries: Vec<ExportEntry> = entries.iter().map(|entry| ExportEntry::new(entry, names)).collect();
+    serde_json::to_writer_pretty(&mut *out, &entries).map_err(std::io::Error::from)?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Writes a self-contained HTML report, entries grouped by day in the given order.
+pub fn write_html(out: &mut impl Write, entries: &[JournalEntry], names: &Names, info: &ReportInfo) -> Result<()> {
+    write!(out, "{}", HTML_HEAD)?;
+    writeln!(out, "<h1>Журнал событий AGRG SH-D</h1>")?;
+    writeln!(out, "<table class=\"info\">")?;
+    if let Some(device) = &info.device {
+        writeln!(out, "<tr><th>Устройство</th><td>{}</td></tr>", escape_html(device.trim()).replace('\n', "<br>"))?;
+    }
+    if let Some(description) = info.description.as_deref().filter(|d| !d.is_empty()) {
+        writeln!(out, "<tr><th>Описание</th><td>{}</td></tr>", escape_html(description))?;
+    }
+    writeln!(out, "<tr><th>Выгружено</th><td>{}</td></tr>", info.exported.format(TIME_FORMAT))?;
+    writeln!(out, "<tr><th>Записей</th><td>{}</td></tr>", entries.len())?;
+    writeln!(out, "</table>")?;
+
+    let mut day = None;
+    for entry in entries {
+        if day != Some(entry.time.date()) {
+            if day.is_some() {
+                writeln!(out, "</table>")?;
+            }
+            day = Some(entry.time.date());
+            writeln!(out, "<h2>{}</h2>", entry.time.format("%d.%m.%Y"))?;
+            writeln!(out, "<table><tr><th>Время</th><th>Код</th><th>Событие</th></tr>")?;
+        }
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>{:02X}</td><td>{}</td></tr>",
+            entry.time.format("%H:%M:%S"),
+            entry.event.code(),
+            escape_html(&names.describe(&entry.event)),
+        )?;
+    }
+    if day.is_some() {
+        writeln!(out, "</table>")?;
+    }
+
+    writeln!(out, "</body>\n</html>")?;
+    Ok(())
+}
+
+const HTML_HEAD: &str = "<!DOCTYPE html>
+<html lang=\"ru\">
+<head>
+<meta charset=\"utf-8\">
+<title>Журнал событий AGRG SH-D</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #999; padding: 0.2em 0.6em; text-align: left; }
+table.info th { background: #eee; }
+h2 { page-break-after: avoid; margin-top: 1.5em; }
+</style>
+</head>
+<body>
+";
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
 
-pub fn parse_journal_entry(raw: Vec<u8>) -> Result<JournalEntry, Box<dyn Error>> {
-    if raw.len() != 16 {
-        return Err("Invalid journal entry length".into());
+/// Parses one 16-byte entry, `None` for an unused (all 0xFF) one.
+pub fn parse_journal_entry(raw: &[u8]) -> Result<Option<JournalEntry>> {
+    if raw.len() != ENTRY_SIZE {
+        return Err(AgrgError::WrongSize { what: "journal entry", expected: ENTRY_SIZE, got: raw.len() });
+    }
+    if raw.iter().all(|&b| b == 0xFF) {
+        return Ok(None);
     }
 
-    // parse timestamp
-    let time = format!(
-        "{:02X}:{:02X}:{:02X}",
-        raw[2], raw[1], raw[0]  // ss, mm, hh
-    );
+    // ss mm hh dd weekday MM yy, same as the device clock
+    let mut clock = [0u8; protocol::CLOCK_SIZE];
+    clock.copy_from_slice(&raw[..protocol::CLOCK_SIZE]);
+    let time = protocol::bcd_to_datetime(&clock)
+        .ok_or_else(|| AgrgError::InvalidDatetime(format!("journal entry {:02X?}", clock)))?;
 
-    // parse date 
-    let date = format!(
-        "20{:02X}-{:02X}-{:02X}", 
-        raw[6], raw[5], raw[3]     
-    );
+    let mut bytes = [0u8; ENTRY_SIZE];
+    bytes.copy_from_slice(raw);
 
-    // parse event type
-    let event_byte = raw[7];
-    let event_type = EVENT_TYPES
-        .get(event_byte as usize)
-        .unwrap_or(&"Unknown")
-        .to_string();
+    Ok(Some(JournalEntry {
+        time,
+        event: JournalEvent::decode(raw[7], raw[8], &raw[9..16]),
+        raw: Some(bytes),
+    }))
+}
 
-    // parse user ID
-    let user_id = raw[8]; 
+/// Something off about the journal ring, by memory address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalIssue {
+    /// Entry whose timestamp is not valid BCD.
+    Corrupted { addr: usize },
+    /// Unused slots between two entries.
+    Gap { addr: usize, slots: usize },
+    /// Entry older than the one written before it, e.g. after a clock reset.
+    OutOfOrder { addr: usize },
+}
+
+impl fmt::Display for JournalIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalIssue::Corrupted { addr } => write!(f, "{:04X}: повреждённая запись", addr),
+            JournalIssue::Gap { addr, slots } => write!(f, "{:04X}: пропуск, пустых записей {}", addr, slots),
+            JournalIssue::OutOfOrder { addr } => write!(f, "{:04X}: нарушен порядок времени", addr),
+        }
+    }
+}
 
-    // parse data
-    let data = raw[9..16].to_vec();
+/// Journal ring read back in the order the handle wrote it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderedJournal {
+    /// Oldest first.
+    pub entries: Vec<JournalEntry>,
+    /// Address the handle writes the next entry to.
+    pub head: usize,
+    /// The ring is full and the oldest entries have been overwritten.
+    pub wrapped: bool,
+    pub issues: Vec<JournalIssue>,
+}
 
+enum Slot {
+    Empty,
+    Corrupted,
+    Used(JournalEntry),
+}
 
-    let res = JournalEntry {
-        timestamp: format!("{} {}", date, time),
-        event_type,
-        user_id,
-        data,
+/// Orders a journal region starting at `JOURNAL.start` in the order the handle wrote it.
+///
+/// The handle writes entries one after another and wraps back to the start,
+/// so the write head is the first empty slot after the written ones. Times
+/// are only looked at when that is ambiguous: with several runs of empty
+/// slots the newest entry before one wins, and a full ring is taken to
+/// continue where the time drops. A clock reset then shows up as
+/// `OutOfOrder` instead of moving the head.
+pub fn order(journal: &[u8]) -> OrderedJournal {
+    let slots: Vec<Slot> = journal.chunks_exact(ENTRY_SIZE)
+        .map(|chunk| match parse_journal_entry(chunk) {
+            Ok(Some(entry)) => Slot::Used(entry),
+            Ok(None) => Slot::Empty,
+            Err(_) => Slot::Corrupted,
+        })
+        .collect();
+    let count = slots.len();
+    let addr = |index: usize| JOURNAL.start + index * ENTRY_SIZE;
+
+    let time = |slot: &Slot| match slot {
+        Slot::Used(entry) => Some(entry.time),
+        _ => None,
+    };
+    // written slots followed by an empty one, corrupted entries were written too
+    let boundaries: Vec<usize> = (0..count)
+        .filter(|&i| !matches!(slots[i], Slot::Empty) && matches!(slots[(i + 1) % count], Slot::Empty))
+        .collect();
+    let last_written = match boundaries.as_slice() {
+        [] => (0..count)
+            .filter_map(|i| match (&slots[i], &slots[(i + 1) % count]) {
+                (Slot::Used(entry), Slot::Used(next)) if next.time >= entry.time => None,
+                (Slot::Used(entry), _) => Some((entry.time, i)),
+                _ => None,
+            })
+            .max()
+            .map(|(_, i)| i),
+        [only] => Some(*only),
+        several => several.iter().max_by_key(|&&i| time(&slots[i])).copied(),
     };
-    Ok(res)
+    let head = last_written.map(|i| (i + 1) % count).unwrap_or_default();
+
+    let mut ordered = OrderedJournal {
+        head: addr(head),
+        wrapped: !matches!(slots.get(head), Some(Slot::Empty) | None),
+        ..OrderedJournal::default()
+    };
+
+    // empty slots right after the head are the unused part of the ring, not a gap
+    let mut gap: Option<(usize, usize)> = None;
+    for i in (head..count).chain(0..head) {
+        match &slots[i] {
+            Slot::Empty => match &mut gap {
+                Some((_, slots)) => *slots += 1,
+                None => gap = Some((i, 1)),
+            },
+            Slot::Corrupted => ordered.issues.push(JournalIssue::Corrupted { addr: addr(i) }),
+            Slot::Used(entry) => {
+                if let (Some((start, slots)), false) = (gap.take(), ordered.entries.is_empty()) {
+                    ordered.issues.push(JournalIssue::Gap { addr: addr(start), slots });
+                }
+                if ordered.entries.last().is_some_and(|last| entry.time < last.time) {
+                    ordered.issues.push(JournalIssue::OutOfOrder { addr: addr(i) });
+                }
+                ordered.entries.push(entry.clone());
+            },
+        }
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // user access entry at "YYYY-MM-DD HH:MM"
+    fn entry(time: &str, slot: u8) -> Vec<u8> {
+        let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap();
+        let mut raw = protocol::datetime_to_bcd(time).to_vec();
+        raw.extend([0x01, slot]);
+        raw.resize(ENTRY_SIZE, 0xFF);
+        raw
+    }
+
+    fn ring(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut journal = entries.concat();
+        journal.resize(JOURNAL.len(), 0xFF);
+        journal
+    }
+
+    fn slots(ordered: &OrderedJournal) -> Vec<u8> {
+        ordered.entries.iter()
+            .map(|entry| match entry.event {
+                JournalEvent::UserAccess { slot } => slot,
+                _ => panic!("unexpected event {:?}", entry.event),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_ring() {
+        let ordered = order(&ring(&[]));
+        assert_eq!(ordered.head, JOURNAL.start);
+        assert!(!ordered.wrapped);
+        assert!(ordered.entries.is_empty());
+        assert!(ordered.issues.is_empty());
+    }
+
+    #[test]
+    fn unwrapped_ring() {
+        let ordered = order(&ring(&[
+            entry("2025-05-01 10:00", 1),
+            entry("2025-05-01 11:00", 2),
+            entry("2025-05-02 09:00", 3),
+        ]));
+        assert_eq!(ordered.head, 0x1030);
+        assert!(!ordered.wrapped);
+        assert_eq!(slots(&ordered), [1, 2, 3]);
+        assert!(ordered.issues.is_empty());
+    }
+
+    #[test]
+    fn clock_reset_keeps_the_head() {
+        let ordered = order(&ring(&[
+            entry("2025-05-01 10:00", 1),
+            entry("2025-05-01 11:00", 2),
+            entry("2000-01-01 00:01", 3),
+            entry("2000-01-01 00:02", 4),
+        ]));
+        assert_eq!(ordered.head, 0x1040);
+        assert!(!ordered.wrapped);
+        assert_eq!(slots(&ordered), [1, 2, 3, 4]);
+        assert_eq!(ordered.issues, [JournalIssue::OutOfOrder { addr: 0x1020 }]);
+    }
+
+    #[test]
+    fn wrapped_ring() {
+        let count = JOURNAL.len() / ENTRY_SIZE;
+        let start = NaiveDateTime::parse_from_str("2025-01-01 00:00", "%Y-%m-%d %H:%M").unwrap();
+        // the newest 10 entries overwrote the start of the ring
+        let journal: Vec<Vec<u8>> = (0..count)
+            .map(|i| {
+                let age = match i < 10 {
+                    true => count - 10 + i,
+                    false => i - 10,
+                };
+                let time = start + chrono::TimeDelta::minutes(age as i64);
+                entry(&time.format("%Y-%m-%d %H:%M").to_string(), (age % 255) as u8)
+            })
+            .collect();
+
+        let ordered = order(&ring(&journal));
+        assert_eq!(ordered.head, JOURNAL.start + 10 * ENTRY_SIZE);
+        assert!(ordered.wrapped);
+        assert_eq!(ordered.entries.len(), count);
+        assert!(ordered.entries.windows(2).all(|pair| pair[0].time < pair[1].time));
+        assert!(ordered.issues.is_empty());
+    }
+
+    #[test]
+    fn corrupted_entry_is_reported_and_skipped() {
+        let mut broken = entry("2025-05-01 10:30", 9);
+        broken[0] = 0xAA;
+        let ordered = order(&ring(&[
+            entry("2025-05-01 10:00", 1),
+            broken,
+            entry("2025-05-01 11:00", 2),
+        ]));
+        assert_eq!(ordered.head, 0x1030);
+        assert_eq!(slots(&ordered), [1, 2]);
+        assert_eq!(ordered.issues, [JournalIssue::Corrupted { addr: 0x1010 }]);
+    }
+
+    #[test]
+    fn corrupted_last_entry_still_ends_the_ring() {
+        let mut broken = entry("2025-05-01 12:00", 9);
+        broken[2] = 0xFA;
+        let ordered = order(&ring(&[
+            entry("2025-05-01 10:00", 1),
+            entry("2025-05-01 11:00", 2),
+            broken,
+        ]));
+        assert_eq!(ordered.head, 0x1030);
+        assert!(!ordered.wrapped);
+        assert_eq!(slots(&ordered), [1, 2]);
+    }
+
+    fn logged(time: &str, event: JournalEvent) -> JournalEntry {
+        JournalEntry { time: NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap(), event, raw: None }
+    }
+
+    // slot 1 is held by `name`, nothing is stored on disk
+    fn names(name: &str) -> Names {
+        let mut roster = crate::utils::roster::Roster::open_at(std::env::temp_dir().join("agrg-no-such-roster.json")).unwrap();
+        roster.set(1, crate::utils::roster::Holder { name: name.into(), note: String::new() });
+        Names::new(Some(&roster), &crate::utils::image::MemoryImage::default())
+    }
+
+    fn csv(entries: &[JournalEntry], names: &Names, options: CsvOptions) -> String {
+        let mut out = Vec::new();
+        write_csv(&mut out, entries, names, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_columns_follow_the_header() {
+        let entries = [logged("2025-05-01 10:00", JournalEvent::UserAccess { slot: 1 })];
+        let out = csv(&entries, &names("Иванов"), CsvOptions::default());
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines[0], CSV_HEADER.join(","));
+        assert_eq!(lines[1], "2025-05-01 10:00:00,01,Пользователь,1,Иванов,,");
+    }
+
+    #[test]
+    fn csv_delimiter_and_bom() {
+        let entries = [logged("2025-05-01 10:00", JournalEvent::PowerOn)];
+
+        let plain = csv(&entries, &names(""), CsvOptions::default());
+        assert!(plain.starts_with("Timestamp,Code,"));
+
+        let excel = csv(&entries, &names(""), CsvOptions { delimiter: Delimiter::Semicolon, bom: true });
+        assert!(excel.starts_with("\u{FEFF}Timestamp;Code;"));
+
+        let tabs = csv(&entries, &names(""), CsvOptions { delimiter: Delimiter::Tab, bom: false });
+        assert!(tabs.starts_with("Timestamp\tCode\t"));
+    }
+
+    #[test]
+    fn csv_quotes_names_with_the_delimiter() {
+        let entries = [logged("2025-05-01 10:00", JournalEvent::UserAccess { slot: 1 })];
+        let names = names("Иванов, И.");
+
+        assert!(csv(&entries, &names, CsvOptions::default()).contains(",\"Иванов, И.\","));
+        assert!(csv(&entries, &names, CsvOptions { delimiter: Delimiter::Semicolon, bom: false }).contains(";Иванов, И.;"));
+    }
+
+    #[test]
+    fn json_field_names() {
+        let entries = [
+            logged("2025-05-01 10:00", JournalEvent::UserAccess { slot: 1 }),
+            logged("2025-05-01 10:05", JournalEvent::UnknownUid { uid: "04a1b2".into() }),
+        ];
+        let mut out = Vec::new();
+        write_json(&mut out, &entries, &names("Иванов")).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(json[0], serde_json::json!({
+            "time": "2025-05-01 10:00:00",
+            "code": "01",
+            "event": "Пользователь",
+            "slot": 1,
+            "holder": "Иванов",
+        }));
+        let keys: Vec<&String> = json[1].as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["code", "event", "time", "uid"]);
+        assert_eq!(json[1]["uid"], "04A1B2");
+    }
+
+    fn html(entries: &[JournalEntry], names: &Names) -> String {
+        let info = ReportInfo { device: None, description: None, exported: entries[0].time };
+        let mut out = Vec::new();
+        write_html(&mut out, entries, names, &info).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn html_escapes_holder_names() {
+        let entries = [logged("2025-05-01 10:00", JournalEvent::UserAccess { slot: 1 })];
+        let out = html(&entries, &names("<Tom & \"Jerry\">"));
+
+        assert!(out.contains("&lt;Tom &amp; &quot;Jerry&quot;&gt; (слот 1)"));
+        assert!(!out.contains("<Tom"));
+    }
+
+    #[test]
+    fn html_groups_entries_by_day() {
+        let entries = [
+            logged("2025-05-01 10:00", JournalEvent::PowerOn),
+            logged("2025-05-01 18:00", JournalEvent::HandleLocked),
+            logged("2025-05-03 09:00", JournalEvent::PowerOn),
+        ];
+        let out = html(&entries, &names(""));
+
+        assert_eq!(out.matches("<h2>").count(), 2);
+        let second_day = out.find("<h2>03.05.2025</h2>").unwrap();
+        assert!(out.find("<h2>01.05.2025</h2>").unwrap() < second_day);
+        assert!(out[..second_day].contains("<td>18:00:00</td><td>08</td>"));
+        assert!(out[second_day..].contains("<td>09:00:00</td><td>00</td>"));
+    }
+
+    fn day(date: &str) -> Option<NaiveDate> {
+        Some(NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap())
+    }
+
+    fn sample_journal() -> Vec<JournalEntry> {
+        vec![
+            logged("2025-05-01 08:00", JournalEvent::PowerOn),
+            logged("2025-05-01 09:00", JournalEvent::UserAccess { slot: 1 }),
+            logged("2025-05-02 09:00", JournalEvent::UserAccess { slot: 2 }),
+            logged("2025-05-02 10:00", JournalEvent::UnknownUid { uid: "04a1b2c3".into() }),
+            logged("2025-05-03 09:00", JournalEvent::UserAccess { slot: 1 }),
+            logged("2025-05-04 09:00", JournalEvent::UnknownUid { uid: "deadbeef".into() }),
+        ]
+    }
+
+    // times of the matching entries, also checks counts().all against matches()
+    fn matched(filter: &JournalFilter) -> Vec<String> {
+        let entries = sample_journal();
+        let matched = filter.apply(&entries);
+        assert_eq!(filter.counts(&entries).all, matched.len());
+        assert_eq!(matched.len(), entries.iter().filter(|e| filter.matches(e)).count());
+        matched.iter().map(|e| e.time.format("%d %H").to_string()).collect()
+    }
+
+    #[test]
+    fn filter_by_dates() {
+        let filter = JournalFilter { from: day("2025-05-02"), to: day("2025-05-03"), ..Default::default() };
+        assert_eq!(matched(&filter), ["02 09", "02 10", "03 09"]);
+
+        let filter = JournalFilter { to: day("2025-05-01"), ..Default::default() };
+        assert_eq!(matched(&filter), ["01 08", "01 09"]);
+    }
+
+    #[test]
+    fn filter_by_kind_slot_and_uid() {
+        let filter = JournalFilter { kind: Some(EventKind::UnknownUid), ..Default::default() };
+        assert_eq!(matched(&filter), ["02 10", "04 09"]);
+
+        let filter = JournalFilter { slot: Some(1), ..Default::default() };
+        assert_eq!(matched(&filter), ["01 09", "03 09"]);
+
+        let filter = JournalFilter { uid: " A1B2 ".into(), ..Default::default() };
+        assert_eq!(matched(&filter), ["02 10"]);
+    }
+
+    #[test]
+    fn combined_filter_needs_every_criterion() {
+        let filter = JournalFilter {
+            from: day("2025-05-02"),
+            kind: Some(EventKind::UserAccess),
+            slot: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(matched(&filter), ["03 09"]);
+        assert_eq!(
+            filter.counts(&sample_journal()),
+            FilterCounts { dates: 4, kind: 3, slot: 2, uid: 6, all: 1 },
+        );
+
+        assert!(JournalFilter::default().is_empty());
+        assert_eq!(matched(&JournalFilter::default()).len(), 6);
+    }
 }
@@ -1,198 +1,117 @@
+pub mod archive;
+pub mod backup;
 pub mod cards;
+pub mod container;
+pub mod diff;
+pub mod error;
+pub mod image;
 pub mod journal;
+pub mod protocol;
+pub mod roster;
 pub mod settings;
-
-use std::error::Error;
-type Пенис = dyn Error;
-
-use std::{io::{self, Read, Write},
-    time::Duration,
-    sync::{
-        Arc, Mutex
-    }
-};
-
-use lazy_static::lazy_static;
-
-lazy_static! {
-    static ref PORT: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
-}
-
-
-pub fn set_port(port: String) {
-    let mut global_port = PORT.lock().unwrap();
-    *global_port = port;
-    println!("Port set to: {}", *global_port);
-}
-
-pub fn check_handle(port: String) -> bool {
-    set_port(port);
-    match get_datetime() {
-        Ok(val) => {
-            val != []
-        },
-        Err(_) => false,
+pub mod sim;
+pub mod stats;
+pub mod transfer;
+pub mod transport;
+
+use error::{AgrgError, Result};
+use protocol::{Command, Response};
+use transfer::{Progress, RetryPolicy, Transfer};
+use transport::{SerialTransport, Transport};
+
+/// Opens the port and pings it, returns the transport if a handle answered.
+pub fn check_handle(port: &str) -> Option<SerialTransport> {
+    let mut transport = SerialTransport::open(port).ok()?;
+
+    // any clock reading will do, an unset clock still means a handle answered
+    match get_datetime(&mut transport) {
+        Ok(val) if val.len() == protocol::CLOCK_SIZE => Some(transport),
+        _ => None,
     }
 }
 
-pub fn scan_ports() -> Option<String> {
-    println!("Scanning ports");
+pub fn scan_ports() -> Option<SerialTransport> {
+    eprintln!("Scanning ports");
     let ports = get_available_ports();
-    println!("Found ports: {:?}", &ports);
+    eprintln!("Found ports: {:?}", &ports);
 
     match ports {
         Some(ports) => {
-            
-            if ports.len() == 1 {
-                if check_handle(ports[0].clone()) { return Some(ports[0].clone()) }
-            }
-            
             for port in ports {
-                if check_handle(port.clone()) {
-                    println!("found handle on port {}", port);
-                    return Some(port)
-                    
+                if let Some(transport) = check_handle(&port) {
+                    eprintln!("found handle on port {}", port);
+                    return Some(transport)
                 };
             }
-            set_port(String::new());
             None
         },
         None => { println!("handle not found"); None }
     }
 }
 
-// static mut PORT: String = String::new();
-
-//TODO: implement passing port as an arg
-fn atomic_serial_exchange(bin_message: Vec<u8>) -> Result<Vec<u8>, Box<Пенис>> {
-    // config or something??
-//  let settings = SerialPortSettings {
-//        baud_rate: 38400,
-//        data_bits: serialport::DataBits::Eight,
-//        stop_bits: serialport::StopBits::One,
-//        parity: serialport::Parity::None,
-//        flow_control: serialport::FlowControl::None,
-//        timeout: Duration::from_millis(100),
-//    };
-
-    // open
-
-    let port_name = PORT.lock().unwrap().clone();
-
-
-    
-        let mut port = serialport::new(
-            port_name, 
-            38400 
-        ).open()?; 
-    
-        // clear buffer
-        port.flush()?;
-
-        // send
-        port.write_all(&bin_message)?;
-        port.flush()?;
-
-        // wait for response
-        std::thread::sleep(Duration::from_millis(50));
-
-        // read response
-        let mut rx = Vec::new();
-        let mut serial_buf: Vec<u8> = vec![0; 16]; // buffer
-        
-        loop {
-            match port.read(&mut serial_buf) {
-                Ok(t) => {
-                    rx.extend_from_slice(&serial_buf[..t]);
-                    // break if less bytes than in buffer are read
-                    if t < serial_buf.len() {
-                        break;
-                    }
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
-                    break; // timeout => no more data
-                }
-                Err(e) => return Err(e.into()),
-            }
-        }
-
-        Ok(rx)
+/// Sends one command and checks the reply against it.
+pub fn request(transport: &mut dyn Transport, command: &Command) -> Result<Response> {
+    let reply = transport.exchange(&command.encode()?, command.reply_len())?;
+    Ok(Response::decode(command, &reply)?)
 }
 
-// fn atomic_serial_exchange(message: Vec<u8>) -> Result<Vec<u8>, Box<Пенис>>{
-//     let mut file = OpenOptions::new()
-//         .append(true)
-//         .create(true)
-//         .open("log.txt")?;
-
-//         for byte in &message {
-//             write!(file, "{:02x}", byte)?;
-//         }
-
-//         writeln!(file, "\n");
-    
-//     Ok(Vec::new())
-// }
-
-fn serial_write(addr: Vec<u8>, mut data: Vec<u8>) -> Result<(), Box<dyn Error>> {
-    let mut tx = vec![0x02, 0x10];
-    tx.splice(1..1, addr);
-    tx.append(&mut data);
-
-    atomic_serial_exchange(tx)?;
-    
+fn serial_write(transport: &mut dyn Transport, addr: u16, data: Vec<u8>) -> Result<()> {
+    request(transport, &Command::Write { addr, data })?;
     Ok(())
 }
 
 // datetime related stuffs
 
-pub fn datetime_to_bytes(datetime: String) -> Result<Vec<u8>, Box<dyn Error>> {
+pub fn datetime_to_bytes(datetime: String) -> Result<Vec<u8>> {
     // split into time and date parts
-    let (time_str, date_str) = datetime.split_once(' ').ok_or("Invalid format: missing space")?;
+    let invalid = |msg: &str| AgrgError::InvalidDatetime(format!("{}: {}", msg, datetime));
+    let (time_str, date_str) = datetime.split_once(' ').ok_or_else(|| invalid("missing space"))?;
     
     // split time
     let time_parts: Vec<&str> = time_str.split(':').collect();
     if time_parts.len() != 3 {
-        return Err("Invalid time format".into());
+        return Err(invalid("invalid time format"));
     }
     let (hours, minutes, seconds) = (time_parts[0], time_parts[1], time_parts[2]);
     
     // split date
     let date_parts: Vec<&str> = date_str.split('.').collect();
     if date_parts.len() != 3 {
-        return Err("Invalid date format".into());
+        return Err(invalid("invalid date format"));
     }
     let (day, month, year) = (date_parts[0], date_parts[1], date_parts[2]);
-    let year_last_two = year.get((year.len().saturating_sub(2))..).ok_or("Year too short")?;
+    let year_last_two = year.get((year.len().saturating_sub(2))..).ok_or_else(|| invalid("year too short"))?;
+    let bcd = |part: &str| u8::from_str_radix(part, 16).map_err(|_| invalid("not a number"));
     
     // parse each part as hex to u8, add unused anywhere but still important weekday
     Ok(vec![
-        u8::from_str_radix(seconds, 16)?,
-        u8::from_str_radix(minutes, 16)?,
-        u8::from_str_radix(hours, 16)?,
-        u8::from_str_radix(day, 16)?,
+        bcd(seconds)?,
+        bcd(minutes)?,
+        bcd(hours)?,
+        bcd(day)?,
         0x00,
-        u8::from_str_radix(month, 16)?,
-        u8::from_str_radix(year_last_two, 16)?,
+        bcd(month)?,
+        bcd(year_last_two)?,
     ])
 }
 
-pub fn get_datetime() -> Result<Vec<u8>, Box<dyn Error>> {
+/// Raw clock reading, a reset clock is not valid BCD: check it with `protocol::bcd_to_datetime`.
+pub fn get_datetime(transport: &mut dyn Transport) -> Result<Vec<u8>> {
     // some internal code, reference protocol documentation for details
-    atomic_serial_exchange(vec![0x01, 0x00, 0x00, 0x00])
-    
+    Ok(request(transport, &Command::ReadClock)?.into_bytes())
 }
 
-pub fn set_datetime(datetime: String) -> Result<Vec<u8>, Box<dyn Error>>{
-    //concat bytes into a single string
-    let mut tx = vec![0x00, 0x00, 0x00, 0x07];
-    let mut datetime_bytes = datetime_to_bytes(datetime)?;
-    
-    tx.append(&mut datetime_bytes);
+pub fn set_datetime(transport: &mut dyn Transport, datetime: String) -> Result<Vec<u8>>{
+    let clock = datetime_to_bytes(datetime)?
+        .try_into()
+        .map_err(|_| AgrgError::InvalidDatetime(String::from("invalid length")))?;
 
-    atomic_serial_exchange(tx)
+    Ok(request(transport, &Command::SetClock(clock))?.into_bytes())
 }
 
+// extra ports to offer besides the detected ones, e.g. the pty of sh-d-bridge
+pub const EXTRA_PORTS_ENV: &str = "AGRG_EXTRA_PORTS";
+
 pub fn get_available_ports() -> Option<Vec<String>> {
     serialport::available_ports()
         .ok()
@@ -201,115 +120,179 @@ pub fn get_available_ports() -> Option<Vec<String>> {
                 .into_iter()
                 //.filter(|p| matches!(p.port_type, serialport::SerialPortType::UsbPort(_)))
                 .map(|p| p.port_name)
+                .chain(extra_ports())
                 .collect()
         })
 }
 
-pub fn mem_dump() -> Result<Vec<u8>, Box<dyn Error>> {
-    let mut rx_vec: Vec<u8> = vec![];
+fn extra_ports() -> Vec<String> {
+    match std::env::var(EXTRA_PORTS_ENV) {
+        Ok(list) => list.split(',')
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Reads the whole memory into `dump.data`, resuming from `dump.next_addr`.
+///
+/// `on_block` sees the progress before every block and cancels the dump by returning false.
+pub fn mem_dump(
+    transport: &mut dyn Transport,
+    policy: &RetryPolicy,
+    dump: &mut Transfer,
+    on_block: &mut dyn FnMut(Progress) -> bool,
+) -> Result<()> {
+    dump.progress.blocks_total = image::IMAGE_SIZE / 32;
+
+    for base_addr in (dump.next_addr..image::IMAGE_SIZE).step_by(32) {
+        dump.checkpoint(on_block)?;
+
+        let command = Command::Read { addr: base_addr as u16, len: 0x20 };
+
+        let mut rx_part = dump.step(policy, base_addr, 32, || {
+            Ok(request(transport, &command)?.into_bytes())
+        })?;
+        eprintln!("{:04X}: {:X?}", base_addr, &rx_part);
+        // the journal is a ring, free slots say nothing about where it ends
+        dump.data.append(&mut rx_part);
+    }
+    dump.done = true;
+    eprintln!("len:{:?}", dump.data.len());
+    Ok(())
+}
 
-    for base_addr in (0x0000..=0x7FFF).step_by(32) {
-        let addr_bytes = (base_addr as u16).to_be_bytes();
-        let command = vec![0x03, addr_bytes[0], addr_bytes[1], 0x20];
+/// Reads `dump.next_addr..end` into `dump.data` without looking at the contents,
+/// e.g. just settings and cards.
+pub fn mem_read(transport: &mut dyn Transport, policy: &RetryPolicy, dump: &mut Transfer, end: usize) -> Result<()> {
+    for base_addr in (dump.next_addr..end.min(image::IMAGE_SIZE)).step_by(32) {
+        let command = Command::Read { addr: base_addr as u16, len: 0x20 };
 
-        let mut rx_part = atomic_serial_exchange(command)?;
-        println!("{:04X}: {:X?}", base_addr, &rx_part);
-        if (base_addr >= 0x1000 && rx_part == vec![0xff; 32]) || (rx_part) == vec![] {
-           return Ok(rx_vec);
-        };
-        rx_vec.append(&mut rx_part);
+        let mut rx_part = dump.step(policy, base_addr, 32, || {
+            Ok(request(transport, &command)?.into_bytes())
+        })?;
+        dump.data.append(&mut rx_part);
     }
-    println!("len:{:?}", rx_vec.len());
-    println!("{:X?}", rx_vec[0]);
-    Ok(rx_vec)
+    dump.done = true;
+    Ok(())
 }
 
-pub fn mem_upload(data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
-
-    for base_addr in (0x0000..0x1000).step_by(16) {
-        if base_addr == 0x0000 {
-            let mut settings1 = data[0x0000..0x0008].to_vec();
-            let mut settings2 = data[0xA..=0xF].to_vec();
-            let mut message1 = vec![0x02, 0x00, 0x00, 0x08];
-            let mut message2 = vec![0x02, 0x00, 0x0A, 0x06];
-            
-            message1.append(&mut settings1);
-            message2.append(&mut settings2);
-
-            atomic_serial_exchange(message1)?;
-            atomic_serial_exchange(message2)?;
-            
-
-        } else {
-            let addr = (base_addr as u16).to_be_bytes();
-            println!("{:4X}: {:X?}", base_addr, &data[(base_addr as usize)..(base_addr as usize)+16].to_vec());
-            serial_write(
-                vec![addr[0], addr[1]],
-                data[(base_addr as usize)..(base_addr as usize)+16].to_vec()
-            )?;
-        }
+/// (address, length) of every write `mem_upload` makes, in order.
+pub fn upload_blocks() -> Vec<(usize, usize)> {
+    // bytes 0x08..0x0A are left untouched
+    let mut blocks = vec![(0x0000, 8), (0x000A, 6)];
+    blocks.extend(image::CARDS.step_by(16).map(|addr| (addr, 16)));
+    blocks
+}
+
+/// Writes settings and cards from `upload.data`, resuming from `upload.next_addr`.
+///
+/// With `upload.baseline` set only the blocks that differ from it are sent.
+/// `on_block` works like in `mem_dump`.
+pub fn mem_upload(
+    transport: &mut dyn Transport,
+    policy: &RetryPolicy,
+    upload: &mut Transfer,
+    on_block: &mut dyn FnMut(Progress) -> bool,
+) -> Result<()> {
+    if upload.data.len() < image::CARDS.end {
+        return Err(AgrgError::WrongSize { what: "image", expected: image::CARDS.end, got: upload.data.len() });
     }
+
+    let resume_from = upload.next_addr;
+    let blocks: Vec<(usize, usize)> = diff::changed_blocks(upload.baseline.as_deref(), &upload.data)
+        .into_iter()
+        .filter(|(addr, _)| *addr >= resume_from)
+        .collect();
+    eprintln!("{} blocks to write", blocks.len());
+    upload.progress.blocks_total = upload.progress.blocks_done + blocks.len();
+
+    for (addr, len) in blocks {
+        upload.checkpoint(on_block)?;
+        let block = upload.data[addr..addr + len].to_vec();
+        eprintln!("{:4X}: {:X?}", addr, &block);
+
+        upload.step(policy, addr, len, || serial_write(transport, addr as u16, block.clone()))?;
+    }
+    upload.done = true;
     Ok(())
 }
 
-// no prog mode here
-// pub fn get_text() -> Option<String> {
-//     println!("getting text");
-//     match atomic_serial_exchange(vec![131, 0x00, 0x00, 64]) {
-//         Ok(res) => match res.as_slice() {
-//             [] => { println!("no response on text"); return None },
-//             _ => Some(
-//                 match String::from_utf8(cards::trim_empty(res)) {
-//                     Ok(thing) => { println!("Пришло: {}", &thing); thing },
-//                     Err(_) => { println!("Че реально в utf-8 не перевелось?"); String::new() }
-//                 }
-//             )
-//         }
-//         Err(_) => {println!("error getting text"); None }
-//     }
-// }
+/// Reads back every range `mem_upload` writes and returns the addresses that differ from `data`.
+pub fn verify_upload(transport: &mut dyn Transport, policy: &RetryPolicy, data: &[u8]) -> Result<Vec<u16>> {
+    if data.len() < image::CARDS.end {
+        return Err(AgrgError::WrongSize { what: "image", expected: image::CARDS.end, got: data.len() });
+    }
 
-pub fn get_text() -> Option<String> {
-    println!("getting text");
-    match atomic_serial_exchange(vec![131, 0x00, 0x00, 64]) {
-        Ok(res) => {
-            let cleaned = cards::trim_empty(res);
-            if cleaned.is_empty() {
-                println!("no response on text");
-                return None;
-            }
-            
-            let s = cleaned.iter()
-                .filter_map(|&b| {
-                    // Keep only ASCII characters (0-127)
-                    if b <= 127 {
-                        Some(b as char)
-                    } else {
-                        None  // Discard non-ASCII bytes
-                    }
-                })
-                .collect::<String>();
-            
-            println!("Пришло: {}", &s);
+    let mut stored: Vec<u8> = Vec::with_capacity(image::CARDS.end);
+    for base_addr in (0x0000..image::CARDS.end).step_by(32) {
+        let command = Command::Read { addr: base_addr as u16, len: 0x20 };
+        let (res, _) = policy.run(|| Ok(request(transport, &command)?.into_bytes()));
+        stored.append(&mut res?);
+    }
+
+    let mismatched: Vec<u16> = upload_blocks().into_iter()
+        .filter(|&(addr, len)| stored[addr..addr + len] != data[addr..addr + len])
+        .map(|(addr, _)| addr as u16)
+        .collect();
+
+    for addr in &mismatched {
+        eprintln!("verify mismatch at {:04X}", addr);
+    }
+    Ok(mismatched)
+}
+
+/// Writes the given upload blocks again, e.g. the mismatches found by `verify_upload`.
+pub fn rewrite_blocks(transport: &mut dyn Transport, policy: &RetryPolicy, data: &[u8], addrs: &[u16]) -> Result<()> {
+    for (addr, len) in upload_blocks().into_iter().filter(|(addr, _)| addrs.contains(&(*addr as u16))) {
+        let block = data.get(addr..addr + len)
+            .ok_or(AgrgError::WrongSize { what: "image", expected: image::CARDS.end, got: data.len() })?
+            .to_vec();
+        let (res, _) = policy.run(|| serial_write(transport, addr as u16, block.clone()));
+        res?;
+    }
+    Ok(())
+}
+
+/// Custom description, empty if none is stored.
+pub fn read_text(transport: &mut dyn Transport) -> Result<String> {
+    let cleaned = cards::trim_empty(request(transport, &Command::ReadText)?.into_bytes());
+
+    // Keep only ASCII characters (0-127)
+    Ok(cleaned.iter()
+        .filter(|&&b| b <= 127)
+        .map(|&b| b as char)
+        .collect())
+}
+
+pub fn get_text(transport: &mut dyn Transport) -> Option<String> {
+    eprintln!("getting text");
+    match read_text(transport) {
+        Ok(s) if s.is_empty() => {
+            eprintln!("no response on text");
+            None
+        },
+        Ok(s) => {
+            eprintln!("Пришло: {}", &s);
             Some(s)
-        }
-        Err(_) => {
-            println!("error getting text"); 
+        },
+        Err(e) => {
+            eprintln!("error getting text: {}", e);
             None
         }
     }
 }
 
-pub fn set_text(input: Vec<u8>) {
-    let mut append = input;
-    let mut tx = vec![0x82, 0x00, 0x00, 0x40];
-    
-    tx.append(&mut append);
+/// Stores `text` as the custom description, cut or padded with 0xFF to 64 bytes.
+pub fn write_text(transport: &mut dyn Transport, text: &str) -> Result<()> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.resize(protocol::TEXT_SIZE, 0xFF);
+    let text = bytes.try_into()
+        .map_err(|b: Vec<u8>| AgrgError::WrongSize { what: "text", expected: protocol::TEXT_SIZE, got: b.len() })?;
 
-    match atomic_serial_exchange(tx) {
-        Ok(_) => {},
-        Err(_) => {}
-    }
+    request(transport, &Command::WriteText(text))?;
+    Ok(())
 }
 
 // no prog mode here
@@ -331,15 +314,10 @@ pub fn set_text(input: Vec<u8>) {
 //     }
 // }
 
-pub fn agrg_text_info() -> Option<String> {
-    match atomic_serial_exchange(vec![0x11, 0x00, 0x00, 0xFF]) {
+pub fn agrg_text_info(transport: &mut dyn Transport) -> Option<String> {
+    match request(transport, &Command::ReadInfo) {
         Ok(res) => {
-            if res.is_empty() {
-                println!("Empty response after trimming");
-                return None;
-            }
-
-            let s = res.iter()
+            let s = res.into_bytes().iter()
                 .filter_map(|&b| {
                     // Keep only ASCII characters (0-127)
                     if b <= 127 {
@@ -351,14 +329,14 @@ pub fn agrg_text_info() -> Option<String> {
                 .collect::<String>();
 
             if s.is_empty() {
-                println!("Filtered out all characters as non-ASCII");
+                eprintln!("Filtered out all characters as non-ASCII");
                 None
             } else {
                 Some(s)
             }
         }
-        Err(_) => {
-            println!("Communication error");
+        Err(e) => {
+            eprintln!("Communication error: {}", e);
             None
         }
     }
@@ -0,0 +1,312 @@
+use std::fmt;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+
+// reference protocol documentation for details
+pub const SET_CLOCK: u8 = 0x00;
+pub const READ_CLOCK: u8 = 0x01;
+pub const WRITE: u8 = 0x02;
+pub const READ: u8 = 0x03;
+pub const READ_INFO: u8 = 0x11;
+pub const WRITE_TEXT: u8 = 0x82;
+pub const READ_TEXT: u8 = 0x83;
+
+pub const CLOCK_SIZE: usize = 7;
+pub const TEXT_SIZE: usize = 64;
+pub const INFO_SIZE: usize = 255;
+
+const HEADER_SIZE: usize = 4;
+
+/// Request frame: opcode, 16-bit big-endian address, length, payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    SetClock([u8; CLOCK_SIZE]),
+    ReadClock,
+    Write { addr: u16, data: Vec<u8> },
+    Read { addr: u16, len: u8 },
+    ReadInfo,
+    WriteText([u8; TEXT_SIZE]),
+    ReadText,
+}
+
+/// Decoded device reply, shaped by the command it answers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Ack,
+    Clock([u8; CLOCK_SIZE]),
+    Block(Vec<u8>),
+    Info(Vec<u8>),
+    Text(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    EmptyReply { opcode: u8 },
+    WrongLength { opcode: u8, expected: usize, got: usize },
+    UnexpectedReply { opcode: u8, got: usize },
+    UnknownOpcode(u8),
+    MalformedFrame(usize),
+    PayloadTooLong(usize),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::EmptyReply { opcode } =>
+                write!(f, "no reply to command {:02X}", opcode),
+            ProtocolError::WrongLength { opcode, expected, got } =>
+                write!(f, "reply to command {:02X}: expected {} bytes, got {}", opcode, expected, got),
+            ProtocolError::UnexpectedReply { opcode, got } =>
+                write!(f, "command {:02X} expects no reply, got {} bytes", opcode, got),
+            ProtocolError::UnknownOpcode(opcode) =>
+                write!(f, "unknown opcode {:02X}", opcode),
+            ProtocolError::MalformedFrame(len) =>
+                write!(f, "malformed frame of {} bytes", len),
+            ProtocolError::PayloadTooLong(len) =>
+                write!(f, "payload too long: {} bytes (max 255)", len),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl Command {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Command::SetClock(_) => SET_CLOCK,
+            Command::ReadClock => READ_CLOCK,
+            Command::Write { .. } => WRITE,
+            Command::Read { .. } => READ,
+            Command::ReadInfo => READ_INFO,
+            Command::WriteText(_) => WRITE_TEXT,
+            Command::ReadText => READ_TEXT,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, ProtocolError> {
+        let (addr, len, payload): (u16, u8, &[u8]) = match self {
+            Command::SetClock(clock) => (0x0000, CLOCK_SIZE as u8, clock),
+            Command::ReadClock => (0x0000, 0x00, &[]),
+            Command::Write { addr, data } => {
+                let len = u8::try_from(data.len())
+                    .map_err(|_| ProtocolError::PayloadTooLong(data.len()))?;
+                (*addr, len, data)
+            },
+            Command::Read { addr, len } => (*addr, *len, &[]),
+            Command::ReadInfo => (0x0000, INFO_SIZE as u8, &[]),
+            Command::WriteText(text) => (0x0000, TEXT_SIZE as u8, text),
+            Command::ReadText => (0x0000, TEXT_SIZE as u8, &[]),
+        };
+
+        let addr = addr.to_be_bytes();
+        let mut frame = vec![self.opcode(), addr[0], addr[1], len];
+        frame.extend_from_slice(payload);
+        Ok(frame)
+    }
+
+    pub fn decode(frame: &[u8]) -> Result<Self, ProtocolError> {
+        let [opcode, hi, lo, len, payload @ ..] = frame else {
+            return Err(ProtocolError::MalformedFrame(frame.len()));
+        };
+        let addr = u16::from_be_bytes([*hi, *lo]);
+
+        if payload.len() != payload_len(*opcode, *len) {
+            return Err(ProtocolError::MalformedFrame(frame.len()));
+        }
+
+        match *opcode {
+            SET_CLOCK => payload.try_into()
+                .map(Command::SetClock)
+                .map_err(|_| ProtocolError::MalformedFrame(frame.len())),
+            READ_CLOCK => Ok(Command::ReadClock),
+            WRITE => Ok(Command::Write { addr, data: payload.to_vec() }),
+            READ => Ok(Command::Read { addr, len: *len }),
+            READ_INFO => Ok(Command::ReadInfo),
+            WRITE_TEXT => payload.try_into()
+                .map(Command::WriteText)
+                .map_err(|_| ProtocolError::MalformedFrame(frame.len())),
+            READ_TEXT => Ok(Command::ReadText),
+            other => Err(ProtocolError::UnknownOpcode(other)),
+        }
+    }
+
+    /// Number of reply bytes the device sends back for this command.
+    pub fn reply_len(&self) -> usize {
+        match self {
+            Command::SetClock(_) | Command::Write { .. } | Command::WriteText(_) => 0,
+            Command::ReadClock => CLOCK_SIZE,
+            Command::Read { len, .. } => *len as usize,
+            Command::ReadInfo => INFO_SIZE,
+            Command::ReadText => TEXT_SIZE,
+        }
+    }
+}
+
+impl Response {
+    /// Checks a raw reply against what `command` should produce.
+    pub fn decode(command: &Command, reply: &[u8]) -> Result<Self, ProtocolError> {
+        let opcode = command.opcode();
+        let expected = command.reply_len();
+
+        if expected == 0 {
+            return match reply.len() {
+                0 => Ok(Response::Ack),
+                got => Err(ProtocolError::UnexpectedReply { opcode, got }),
+            };
+        }
+        if reply.is_empty() {
+            return Err(ProtocolError::EmptyReply { opcode });
+        }
+        if reply.len() != expected {
+            return Err(ProtocolError::WrongLength { opcode, expected, got: reply.len() });
+        }
+
+        match command {
+            // a reset clock reads as garbage but the handle is still there,
+            // whoever shows the time checks it with bcd_to_datetime
+            Command::ReadClock => {
+                let mut clock = [0u8; CLOCK_SIZE];
+                clock.copy_from_slice(reply);
+                Ok(Response::Clock(clock))
+            },
+            Command::ReadInfo => Ok(Response::Info(reply.to_vec())),
+            Command::ReadText => Ok(Response::Text(reply.to_vec())),
+            _ => Ok(Response::Block(reply.to_vec())),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Response::Ack => Vec::new(),
+            Response::Clock(clock) => clock.to_vec(),
+            Response::Block(data) | Response::Info(data) | Response::Text(data) => data,
+        }
+    }
+}
+
+// write commands carry `len` payload bytes after the header, reads carry none
+fn payload_len(opcode: u8, len: u8) -> usize {
+    match opcode {
+        SET_CLOCK | WRITE | WRITE_TEXT => len as usize,
+        _ => 0,
+    }
+}
+
+/// Full length of a request frame judging by its 4-byte header.
+pub fn frame_len(header: &[u8]) -> Option<usize> {
+    match header {
+        [opcode, _, _, len, ..] => Some(HEADER_SIZE + payload_len(*opcode, *len)),
+        _ => None,
+    }
+}
+
+fn to_bcd(value: u32) -> u8 {
+    (((value / 10) << 4) | (value % 10)) as u8
+}
+
+fn from_bcd(value: u8) -> Option<u32> {
+    let (hi, lo) = (value >> 4, value & 0x0F);
+    if hi > 9 || lo > 9 {
+        return None;
+    }
+    Some((hi * 10 + lo) as u32)
+}
+
+// ss mm hh dd weekday MM yy, same layout as datetime_to_bytes
+pub fn datetime_to_bcd(time: NaiveDateTime) -> [u8; CLOCK_SIZE] {
+    [
+        to_bcd(time.second()),
+        to_bcd(time.minute()),
+        to_bcd(time.hour()),
+        to_bcd(time.day()),
+        to_bcd(time.weekday().number_from_monday()),
+        to_bcd(time.month()),
+        to_bcd(time.year() as u32 % 100),
+    ]
+}
+
+/// Clock reading as a time, `None` if the clock holds no valid BCD date.
+pub fn bcd_to_datetime(raw: &[u8; CLOCK_SIZE]) -> Option<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(2000 + from_bcd(raw[6])? as i32, from_bcd(raw[5])?, from_bcd(raw[3])?)?
+        .and_hms_opt(from_bcd(raw[2])?, from_bcd(raw[1])?, from_bcd(raw[0])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_frames() {
+        assert_eq!(Command::Read { addr: 0x1234, len: 0x20 }.encode().unwrap(), [READ, 0x12, 0x34, 0x20]);
+        assert_eq!(Command::ReadClock.encode().unwrap(), [READ_CLOCK, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            Command::Write { addr: 0x0010, data: vec![0xAB, 0xCD] }.encode().unwrap(),
+            [WRITE, 0x00, 0x10, 0x02, 0xAB, 0xCD],
+        );
+        assert_eq!(
+            Command::Write { addr: 0, data: vec![0; 256] }.encode(),
+            Err(ProtocolError::PayloadTooLong(256)),
+        );
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let commands = [
+            Command::SetClock([0x56, 0x34, 0x12, 0x01, 0x05, 0x02, 0x30]),
+            Command::ReadClock,
+            Command::Write { addr: 0x0A00, data: vec![1, 2, 3] },
+            Command::Read { addr: 0x7FE0, len: 0x20 },
+            Command::ReadInfo,
+            Command::WriteText([0x41; TEXT_SIZE]),
+            Command::ReadText,
+        ];
+        for command in commands {
+            let frame = command.encode().unwrap();
+            assert_eq!(frame_len(&frame), Some(frame.len()));
+            assert_eq!(Command::decode(&frame).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        assert_eq!(Command::decode(&[READ, 0x00]), Err(ProtocolError::MalformedFrame(2)));
+        // write announcing more payload than it carries
+        assert_eq!(Command::decode(&[WRITE, 0x00, 0x10, 0x04, 0x01]), Err(ProtocolError::MalformedFrame(5)));
+        assert_eq!(Command::decode(&[0x42, 0x00, 0x00, 0x00]), Err(ProtocolError::UnknownOpcode(0x42)));
+    }
+
+    #[test]
+    fn checks_replies() {
+        let read = Command::Read { addr: 0, len: 4 };
+        assert_eq!(Response::decode(&read, &[1, 2, 3, 4]), Ok(Response::Block(vec![1, 2, 3, 4])));
+        assert_eq!(Response::decode(&read, &[]), Err(ProtocolError::EmptyReply { opcode: READ }));
+        assert_eq!(
+            Response::decode(&read, &[1, 2]),
+            Err(ProtocolError::WrongLength { opcode: READ, expected: 4, got: 2 }),
+        );
+
+        let write = Command::Write { addr: 0, data: vec![1] };
+        assert_eq!(Response::decode(&write, &[]), Ok(Response::Ack));
+        assert_eq!(Response::decode(&write, &[0]), Err(ProtocolError::UnexpectedReply { opcode: WRITE, got: 1 }));
+    }
+
+    #[test]
+    fn reset_clock_still_decodes() {
+        for raw in [[0xFF; CLOCK_SIZE], [0x00; CLOCK_SIZE]] {
+            assert_eq!(Response::decode(&Command::ReadClock, &raw), Ok(Response::Clock(raw)));
+            assert_eq!(bcd_to_datetime(&raw), None);
+        }
+    }
+
+    #[test]
+    fn bcd_round_trip() {
+        let time = NaiveDate::from_ymd_opt(2025, 12, 31).unwrap().and_hms_opt(23, 59, 58).unwrap();
+        let raw = datetime_to_bcd(time);
+
+        // Wednesday is day 3
+        assert_eq!(raw, [0x58, 0x59, 0x23, 0x31, 0x03, 0x12, 0x25]);
+        assert_eq!(bcd_to_datetime(&raw), Some(time));
+        assert_eq!(bcd_to_datetime(&[0x00, 0x00, 0x00, 0x31, 0x00, 0x02, 0x25]), None);
+        assert_eq!(bcd_to_datetime(&[0x0A, 0x00, 0x00, 0x01, 0x00, 0x01, 0x25]), None);
+    }
+}
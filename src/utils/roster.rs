@@ -0,0 +1,203 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    archive,
+    cards::CardRow,
+    error::Result,
+    image::{ImportMode, Imported, MemoryImage},
+    journal::JournalEvent,
+};
+
+/// Who holds the card in a slot, kept on this computer only.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Holder {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub note: String,
+}
+
+/// Card holder names of one device by slot, stored next to its journal archive.
+#[derive(Debug, Clone)]
+pub struct Roster {
+    path: PathBuf,
+    holders: BTreeMap<usize, Holder>,
+}
+
+impl Roster {
+    /// Opens the roster of the device with this info string, empty if there is none yet.
+    pub fn open(device: &str) -> Result<Self> {
+        Self::open_at(archive::archive_dir().join(format!("{}.roster.json", archive::file_key(device))))
+    }
+
+    pub fn open_at(path: PathBuf) -> Result<Self> {
+        let holders = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(std::io::Error::from)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, holders })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn holder(&self, slot: usize) -> Option<&Holder> {
+        self.holders.get(&slot)
+    }
+
+    /// Sets the holder of a slot, a blank name and note forget it.
+    pub fn set(&mut self, slot: usize, holder: Holder) {
+        match holder.name.trim().is_empty() && holder.note.trim().is_empty() {
+            true => self.holders.remove(&slot),
+            false => self.holders.insert(slot, holder),
+        };
+    }
+
+    /// Takes the names of an imported card table, a replaced table forgets every other slot.
+    pub fn import(&mut self, imported: &Imported, mode: ImportMode) {
+        if mode == ImportMode::Replace {
+            self.holders.retain(|slot, _| imported.placed.iter().any(|(placed, _)| placed == slot));
+        }
+        for (slot, row) in imported.placed.iter().filter(|(_, row)| !row.name.trim().is_empty()) {
+            let note = self.holder(*slot).map(|holder| holder.note.clone()).unwrap_or_default();
+            self.set(*slot, Holder { name: row.name.trim().to_string(), note });
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_vec_pretty(&self.holders).map_err(std::io::Error::from)?;
+        fs::write(&self.path, json)?;
+        Ok(())
+    }
+}
+
+/// Enrolled slots of the image as a card table, names taken from the roster.
+pub fn card_table(image: &MemoryImage, roster: Option<&Roster>) -> Vec<CardRow> {
+    image.cards()
+        .filter_map(|(slot, card)| card.ok().map(|card| (slot, card)))
+        .filter(|(_, card)| !card.rfid.is_empty() || !card.pin.is_empty())
+        .map(|(slot, card)| CardRow {
+            slot,
+            uid: card.rfid.to_uppercase(),
+            pin: card.pin,
+            name: roster.and_then(|roster| roster.holder(slot)).map(|holder| holder.name.clone()).unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Resolves journal slots and UIDs to card holders, plain events without a roster.
+#[derive(Debug, Clone, Default)]
+pub struct Names {
+    holders: BTreeMap<usize, Holder>,
+    // UID -> slot it is enrolled in now
+    enrolled: HashMap<String, usize>,
+}
+
+impl Names {
+    pub fn new(roster: Option<&Roster>, image: &MemoryImage) -> Self {
+        let enrolled = image.cards()
+            .filter_map(|(slot, card)| card.ok().filter(|card| !card.rfid.is_empty()).map(|card| (card.rfid, slot)))
+            .collect();
+        Self {
+            holders: roster.map(|roster| roster.holders.clone()).unwrap_or_default(),
+            enrolled,
+        }
+    }
+
+    /// "Name (слот N)", or just the slot number without a name.
+    pub fn slot(&self, slot: usize) -> String {
+        match self.name(slot) {
+            Some(name) => format!("{} (слот {})", name, slot),
+            None => slot.to_string(),
+        }
+    }
+
+    /// Slot the UID is enrolled in now.
+    pub fn enrolled_slot(&self, uid: &str) -> Option<usize> {
+        self.enrolled.get(uid).copied()
+    }
+
+    fn name(&self, slot: usize) -> Option<&str> {
+        self.holders.get(&slot).map(|holder| holder.name.trim()).filter(|name| !name.is_empty())
+    }
+
+    /// Holder an event refers to: the user of an access or whoever holds a refused card now.
+    pub fn holder(&self, event: &JournalEvent) -> Option<&str> {
+        match event {
+            JournalEvent::UserAccess { slot } => self.name(*slot as usize),
+            JournalEvent::UnknownUid { uid } => self.name(self.enrolled_slot(uid)?),
+            _ => None,
+        }
+    }
+
+    /// Event as shown in the journal, with slots and enrolled UIDs named.
+    pub fn describe(&self, event: &JournalEvent) -> String {
+        match event {
+            JournalEvent::UserAccess { slot } => format!("{} {}", event.name(), self.slot(*slot as usize)),
+            // the card may have been enrolled after it was refused
+            JournalEvent::UnknownUid { uid } => match self.enrolled.get(uid) {
+                Some(&slot) if self.name(slot).is_some() => format!("{}, сейчас {}", event, self.slot(slot)),
+                Some(&slot) => format!("{}, сейчас слот {}", event, slot),
+                None => event.to_string(),
+            },
+            _ => event.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::cards::Card;
+
+    fn holder(name: &str, note: &str) -> Holder {
+        Holder { name: name.into(), note: note.into() }
+    }
+
+    #[test]
+    fn named_and_unknown_slots() {
+        let mut roster = Roster::open_at(std::env::temp_dir().join("agrg-roster-no-such-file.json")).unwrap();
+        roster.set(1, holder("Иванов", ""));
+        let mut image = MemoryImage::default();
+        image.set_card(1, &Card { rfid: "04a1".into(), pin: String::new() }).unwrap();
+        image.set_card(2, &Card { rfid: "0b0b".into(), pin: String::new() }).unwrap();
+        let names = Names::new(Some(&roster), &image);
+
+        assert_eq!(names.slot(1), "Иванов (слот 1)");
+        assert_eq!(names.slot(2), "2");
+        assert_eq!(names.holder(&JournalEvent::UserAccess { slot: 1 }), Some("Иванов"));
+        assert_eq!(names.holder(&JournalEvent::UserAccess { slot: 2 }), None);
+        assert_eq!(names.holder(&JournalEvent::UnknownUid { uid: "04a1".into() }), Some("Иванов"));
+
+        assert_eq!(names.describe(&JournalEvent::UserAccess { slot: 1 }), "Пользователь Иванов (слот 1)");
+        assert_eq!(names.describe(&JournalEvent::UserAccess { slot: 7 }), "Пользователь 7");
+        assert_eq!(names.describe(&JournalEvent::UnknownUid { uid: "0b0b".into() }), "Незарегистрированный UID 0B0B, сейчас слот 2");
+    }
+
+    #[test]
+    fn save_and_open_round_trip() {
+        let path = std::env::temp_dir().join(format!("agrg-roster-{}", std::process::id())).join("device.roster.json");
+        _ = fs::remove_file(&path);
+
+        let mut roster = Roster::open_at(path.clone()).unwrap();
+        roster.set(3, holder("Петров", "склад"));
+        roster.set(5, holder("Сидорова", ""));
+        roster.set(5, holder(" ", ""));
+        roster.save().unwrap();
+
+        let opened = Roster::open_at(path.clone()).unwrap();
+        assert_eq!(opened.holder(3), Some(&holder("Петров", "склад")));
+        assert_eq!(opened.holder(5), None);
+        _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -1,8 +1,12 @@
+use std::fmt;
+
 use chrono::Local;
 use rfd::FileDialog;
 
+use super::container::{self, Container, PayloadKind};
 
-pub fn export_bin(settings: Vec<u8>, uid: String) -> Result<(), Box<dyn std::error::Error>> {
+/// Saves the settings block as an `.agrg` container tagged with the device info string.
+pub fn export_bin(settings: Vec<u8>, uid: String, device: Option<String>) -> super::error::Result<()> {
     
     let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
     
@@ -12,24 +16,249 @@ pub fn export_bin(settings: Vec<u8>, uid: String) -> Result<(), Box<dyn std::err
         .save_file();
 
     if let Some(path) = file_path {
-        std::fs::write(path, settings)?;
+        std::fs::write(path, Container::new(PayloadKind::Settings, device, settings).to_bytes())?;
     }
 
     Ok(())
 
 }
 
-pub fn import_bin() -> Result<Vec<u8>, std::io::Error> {
+/// Settings from a file picked by the user, `None` if the dialog was closed.
+///
+/// Raw files from before the container are read as they are.
+pub fn import_bin() -> super::error::Result<Option<Vec<u8>>> {
     let file_path = rfd::FileDialog::new()
         .set_title("Импортировать настройки")
         .pick_file();
     
-    if let Some(path) = file_path {
-        std::fs::read(path)
-    }
-    else {
-        Ok(vec![0x00; 16])
+    match file_path {
+        Some(path) => Ok(Some(container::open(std::fs::read(path)?, PayloadKind::Settings)?)),
+        None => Ok(None),
     }
 
     
-}
\ No newline at end of file
+}
+
+/// Names of the option bytes 0..4, in byte order.
+pub const FIELD_NAMES: [&str; 4] = ["Режим работы", "Формат кодонаборной панели", "Формат считывателя", "Режим доступа"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingsError {
+    WrongLength(usize),
+    UnknownValue { field: &'static str, value: u8 },
+    InvalidPinDigit(u8),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::WrongLength(len) =>
+                write!(f, "неверная длина настроек: должно быть {}, получено {}", SETTINGS_SIZE, len),
+            SettingsError::UnknownValue { field, value } =>
+                write!(f, "{}: неизвестное значение 0x{:02X}", field, value),
+            SettingsError::InvalidPinDigit(value) =>
+                write!(f, "неверная цифра PIN: {} (допустимо 0-9)", value),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkMode {
+    Reader,
+    Standalone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinpadFormat {
+    Wiegand6,
+    Wiegand26Hex,
+    Wiegand26Dec,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReaderFormat {
+    Wiegand26,
+    Wiegand34,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessMode {
+    PinOrUid,
+    Pin,
+    Uid,
+    PinAndUid,
+}
+
+impl WorkMode {
+    pub const ALL: [WorkMode; 2] = [WorkMode::Reader, WorkMode::Standalone];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkMode::Reader => "Считыватель",
+            WorkMode::Standalone => "Автономный контроллер",
+        }
+    }
+}
+
+impl PinpadFormat {
+    pub const ALL: [PinpadFormat; 4] = [PinpadFormat::Wiegand6, PinpadFormat::Wiegand26Hex, PinpadFormat::Wiegand26Dec, PinpadFormat::Off];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PinpadFormat::Wiegand6 => "Wiegand6",
+            PinpadFormat::Wiegand26Hex => "Wiegand26(hex)",
+            PinpadFormat::Wiegand26Dec => "Wiegand26(dec)",
+            PinpadFormat::Off => "OFF",
+        }
+    }
+}
+
+impl ReaderFormat {
+    pub const ALL: [ReaderFormat; 3] = [ReaderFormat::Wiegand26, ReaderFormat::Wiegand34, ReaderFormat::Off];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReaderFormat::Wiegand26 => "Wiegand26",
+            ReaderFormat::Wiegand34 => "Wiegand34",
+            ReaderFormat::Off => "OFF",
+        }
+    }
+}
+
+impl AccessMode {
+    pub const ALL: [AccessMode; 4] = [AccessMode::PinOrUid, AccessMode::Pin, AccessMode::Uid, AccessMode::PinAndUid];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccessMode::PinOrUid => "PIN или UID",
+            AccessMode::Pin => "PIN",
+            AccessMode::Uid => "UID",
+            AccessMode::PinAndUid => "PIN и UID",
+        }
+    }
+}
+
+/// Option at `value`, option bytes are indices into `ALL`.
+pub fn option_from_byte<T: Copy>(all: &[T], field: usize, value: u8) -> Result<T, SettingsError> {
+    all.get(value as usize)
+        .copied()
+        .ok_or(SettingsError::UnknownValue { field: FIELD_NAMES[field], value })
+}
+
+/// Byte stored for `option`.
+pub fn option_to_byte<T: PartialEq>(all: &[T], option: &T) -> u8 {
+    all.iter().position(|o| o == option).unwrap_or_default() as u8
+}
+
+macro_rules! display_name {
+    ($($t:ty),*) => {$(
+        impl fmt::Display for $t {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+    )*};
+}
+
+display_name!(WorkMode, PinpadFormat, ReaderFormat, AccessMode);
+
+pub const SETTINGS_SIZE: usize = 16;
+
+/// Settings block at 0x0000..0x0010.
+///
+/// Bytes 4..10 have no known meaning and are carried through unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceSettings {
+    pub work_mode: WorkMode,
+    pub pinpad: PinpadFormat,
+    pub reader: ReaderFormat,
+    pub access: AccessMode,
+    pub reserved: [u8; 6],
+    /// One digit per byte.
+    pub admin_pin: [u8; 6],
+}
+
+impl DeviceSettings {
+    pub fn parse(bytes: &[u8]) -> Result<Self, SettingsError> {
+        if bytes.len() != SETTINGS_SIZE {
+            return Err(SettingsError::WrongLength(bytes.len()));
+        }
+        if let Some(&digit) = bytes[10..16].iter().find(|&&d| d > 9) {
+            return Err(SettingsError::InvalidPinDigit(digit));
+        }
+
+        let mut reserved = [0u8; 6];
+        reserved.copy_from_slice(&bytes[4..10]);
+        let mut admin_pin = [0u8; 6];
+        admin_pin.copy_from_slice(&bytes[10..16]);
+
+        Ok(Self {
+            work_mode: option_from_byte(&WorkMode::ALL, 0, bytes[0])?,
+            pinpad: option_from_byte(&PinpadFormat::ALL, 1, bytes[1])?,
+            reader: option_from_byte(&ReaderFormat::ALL, 2, bytes[2])?,
+            access: option_from_byte(&AccessMode::ALL, 3, bytes[3])?,
+            reserved,
+            admin_pin,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; SETTINGS_SIZE] {
+        let mut bytes = [0u8; SETTINGS_SIZE];
+        bytes[0] = option_to_byte(&WorkMode::ALL, &self.work_mode);
+        bytes[1] = option_to_byte(&PinpadFormat::ALL, &self.pinpad);
+        bytes[2] = option_to_byte(&ReaderFormat::ALL, &self.reader);
+        bytes[3] = option_to_byte(&AccessMode::ALL, &self.access);
+        bytes[4..10].copy_from_slice(&self.reserved);
+        bytes[10..16].copy_from_slice(&self.admin_pin);
+        bytes
+    }
+
+    /// Admin PIN as a digit string.
+    pub fn admin_pin_string(&self) -> String {
+        self.admin_pin.iter().map(|d| d.to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BYTES: [u8; SETTINGS_SIZE] = [1, 2, 0, 3, 0xAA, 0x55, 0x00, 0xFF, 0x12, 0x34, 1, 2, 3, 4, 5, 6];
+
+    #[test]
+    fn round_trip_keeps_reserved_bytes() {
+        let settings = DeviceSettings::parse(&BYTES).unwrap();
+
+        assert_eq!(settings.work_mode, WorkMode::Standalone);
+        assert_eq!(settings.pinpad, PinpadFormat::Wiegand26Dec);
+        assert_eq!(settings.reader, ReaderFormat::Wiegand26);
+        assert_eq!(settings.access, AccessMode::PinAndUid);
+        assert_eq!(settings.reserved, [0xAA, 0x55, 0x00, 0xFF, 0x12, 0x34]);
+        assert_eq!(settings.admin_pin_string(), "123456");
+        assert_eq!(settings.to_bytes(), BYTES);
+    }
+
+    #[test]
+    fn option_past_all_is_unknown() {
+        let mut bytes = BYTES;
+        bytes[2] = ReaderFormat::ALL.len() as u8;
+
+        assert_eq!(
+            DeviceSettings::parse(&bytes),
+            Err(SettingsError::UnknownValue { field: FIELD_NAMES[2], value: 3 }),
+        );
+    }
+
+    #[test]
+    fn pin_digit_above_nine_is_rejected() {
+        let mut bytes = BYTES;
+        bytes[13] = 0x0A;
+
+        assert_eq!(DeviceSettings::parse(&bytes), Err(SettingsError::InvalidPinDigit(0x0A)));
+        assert_eq!(DeviceSettings::parse(&BYTES[..15]), Err(SettingsError::WrongLength(15)));
+    }
+}
@@ -0,0 +1,382 @@
+use chrono::{Local, NaiveDateTime, TimeDelta};
+
+use super::{
+    cards,
+    error::{AgrgError, Result},
+    image::{ENTRY_SIZE, IMAGE_SIZE, JOURNAL},
+    protocol::{bcd_to_datetime, datetime_to_bcd, Command, Response, INFO_SIZE, TEXT_SIZE},
+    transport::{Transport, TransportError},
+};
+
+// name shown in the port list for the built-in demo device
+pub const DEMO_PORT: &str = "Демо-устройство";
+
+/// In-process SH-D handle.
+///
+/// Speaks the same command set as the real device over the `Transport`
+/// trait and keeps a full 32 KiB memory image: settings at 0x0000, card
+/// slots up to 0x0FFF and the journal ring from 0x1000 to the end.
+pub struct Simulator {
+    memory: Vec<u8>,
+    text: Vec<u8>,
+    info: String,
+    // device clock as an offset from the host clock
+    clock_offset: TimeDelta,
+    // address of the next journal entry to be written
+    journal_head: usize,
+    reply: Vec<u8>,
+}
+
+impl Simulator {
+    /// Blank handle: factory settings, no cards, empty journal.
+    pub fn new(info: &str) -> Self {
+        let mut memory = vec![0xFF; IMAGE_SIZE];
+        memory[0x0000..0x0004].copy_from_slice(&[0x00; 4]);
+        memory[0x000A..0x0010].copy_from_slice(&[0x00; 6]);
+
+        Self {
+            memory,
+            text: vec![0xFF; TEXT_SIZE],
+            info: info.to_string(),
+            clock_offset: TimeDelta::zero(),
+            journal_head: JOURNAL.start,
+            reply: Vec::new(),
+        }
+    }
+
+    /// Handle with a few users and journal events, used as the GUI demo device.
+    pub fn demo() -> Self {
+        let mut sim = Self::new("AGRG SH-D\nSIM v1.0\nSN 00000001");
+
+        sim.memory[0x0000..0x0004].copy_from_slice(&[0x01, 0x01, 0x00, 0x00]);
+        sim.memory[0x000A..0x0010].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        sim.set_text("Demo: office 101");
+
+        let users = [("04A1B2C3D4", "1234"), ("04FF10203040", "5555"), ("0A0B0C0D", "")];
+        for (slot, (rfid, pin)) in users.iter().enumerate() {
+            // demo values are known to be valid
+            _ = sim.set_card(slot + 1, rfid, pin);
+        }
+
+        let now = sim.now();
+        let events: [(i64, u8, u8, [u8; 7]); 6] = [
+            (120, 0x00, 0x00, [0xFF; 7]),
+            (95, 0x01, 0x01, [0xFF; 7]),
+            (94, 0x06, 0x00, [0xFF; 7]),
+            (90, 0x08, 0x00, [0xFF; 7]),
+            (30, 0x02, 0x04, [0xDE, 0xAD, 0xBE, 0xEF, 0xFF, 0xFF, 0xFF]),
+            (5, 0x0A, 0x00, [0xFF; 7]),
+        ];
+        for (minutes_ago, event, user, data) in events {
+            sim.push_event_at(now - TimeDelta::minutes(minutes_ago), event, user, data);
+        }
+
+        sim
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Current device time, host clock plus drift.
+    pub fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local() + self.clock_offset
+    }
+
+    /// Shifts the device clock relative to the host, to simulate drift.
+    pub fn drift(&mut self, delta: TimeDelta) {
+        self.clock_offset += delta;
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.resize(TEXT_SIZE, 0xFF);
+        self.text = bytes;
+    }
+
+    /// Writes a card into 1-based slot `slot`.
+    pub fn set_card(&mut self, slot: usize, rfid: &str, pin: &str) -> Result<()> {
+        if !(1..=255).contains(&slot) {
+            return Err(AgrgError::OutOfRange { what: "slot", index: slot });
+        }
+
+        let mut entry = cards::rfid_to_bytes(rfid.to_string())?;
+        entry.append(&mut cards::pin_to_bytes(pin.to_string())?);
+
+        let addr = slot * ENTRY_SIZE;
+        self.memory[addr..addr + ENTRY_SIZE].copy_from_slice(&entry);
+        Ok(())
+    }
+
+    /// Logs an event at the current device time.
+    pub fn push_event(&mut self, event: u8, user: u8, data: [u8; 7]) {
+        let now = self.now();
+        self.push_event_at(now, event, user, data);
+    }
+
+    /// Logs an event into the journal ring, wrapping back to 0x1000 at the end.
+    pub fn push_event_at(&mut self, time: NaiveDateTime, event: u8, user: u8, data: [u8; 7]) {
+        let mut entry = datetime_to_bcd(time).to_vec();
+        entry.push(event);
+        entry.push(user);
+        entry.extend_from_slice(&data);
+
+        let addr = self.journal_head;
+        self.memory[addr..addr + ENTRY_SIZE].copy_from_slice(&entry);
+
+        self.journal_head += ENTRY_SIZE;
+        if self.journal_head >= JOURNAL.end {
+            self.journal_head = JOURNAL.start;
+        }
+    }
+
+    /// Processes one request frame and returns the reply bytes.
+    pub fn handle(&mut self, frame: &[u8]) -> Vec<u8> {
+        match Command::decode(frame) {
+            Ok(command) => self.execute(command).into_bytes(),
+            // real handle stays silent on garbage
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn execute(&mut self, command: Command) -> Response {
+        match command {
+            Command::ReadClock => Response::Clock(datetime_to_bcd(self.now())),
+            Command::SetClock(clock) => {
+                if let Some(time) = bcd_to_datetime(&clock) {
+                    self.clock_offset = time - Local::now().naive_local();
+                }
+                Response::Ack
+            },
+            Command::Read { addr, len } => {
+                let addr = addr as usize;
+                match self.memory.get(addr..addr + len as usize) {
+                    Some(block) => Response::Block(block.to_vec()),
+                    None => Response::Ack,
+                }
+            },
+            Command::Write { addr, data } => {
+                let addr = addr as usize;
+                if let Some(target) = self.memory.get_mut(addr..addr + data.len()) {
+                    target.copy_from_slice(&data);
+                }
+                Response::Ack
+            },
+            Command::ReadText => Response::Text(self.text.clone()),
+            Command::WriteText(text) => {
+                self.text = text.to_vec();
+                Response::Ack
+            },
+            Command::ReadInfo => {
+                let mut info = self.info.as_bytes().to_vec();
+                info.resize(INFO_SIZE, 0xFF);
+                Response::Info(info)
+            },
+        }
+    }
+}
+
+impl Transport for Simulator {
+    fn send(&mut self, frame: &[u8]) -> Result<()> {
+        self.reply = self.handle(frame);
+        Ok(())
+    }
+
+    fn receive(&mut self, expected: usize) -> Result<Vec<u8>> {
+        let mut reply = std::mem::take(&mut self.reply);
+        TransportError::check(expected, reply.len())?;
+
+        // like on the wire, whatever is past the frame gets dropped
+        reply.truncate(expected);
+        Ok(reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::utils::{
+        self,
+        cards::Card,
+        image::{MemoryImage, CARDS},
+        protocol::CLOCK_SIZE,
+        transfer::{RetryPolicy, Transfer},
+    };
+
+    const POLICY: RetryPolicy = RetryPolicy { attempts: 3, backoff: Duration::ZERO };
+
+    // times out on the exchanges `fails` picks, counted from 0
+    struct Flaky {
+        sim: Simulator,
+        exchanges: usize,
+        fails: fn(usize) -> bool,
+    }
+
+    impl Transport for Flaky {
+        fn send(&mut self, frame: &[u8]) -> Result<()> {
+            self.sim.send(frame)
+        }
+
+        fn receive(&mut self, expected: usize) -> Result<Vec<u8>> {
+            let exchange = self.exchanges;
+            self.exchanges += 1;
+            if (self.fails)(exchange) {
+                self.sim.reply.clear();
+                return Err(TransportError::Timeout { expected }.into());
+            }
+            self.sim.receive(expected)
+        }
+    }
+
+    fn dump(transport: &mut dyn Transport) -> MemoryImage {
+        let mut dump = Transfer::dump();
+        utils::mem_dump(transport, &POLICY, &mut dump, &mut |_| true).unwrap();
+        assert!(dump.done);
+        MemoryImage::from_dump(dump.data).unwrap()
+    }
+
+    #[test]
+    fn dump_reads_the_whole_memory() {
+        let mut sim = Simulator::demo();
+        let image = dump(&mut sim);
+
+        assert_eq!(image.as_bytes(), sim.memory());
+        assert_eq!(image.card(1).unwrap(), Card { rfid: "04a1b2c3d4".into(), pin: "1234".into() });
+    }
+
+    #[test]
+    fn upload_writes_settings_and_cards() {
+        let mut sim = Simulator::demo();
+        let mut image = dump(&mut sim);
+        image.set_card(5, &Card { rfid: "0102".into(), pin: "42".into() }).unwrap();
+        image.set_setting(0, 0x00).unwrap();
+
+        let mut upload = Transfer::upload(image.upload_region().to_vec());
+        utils::mem_upload(&mut sim, &POLICY, &mut upload, &mut |_| true).unwrap();
+
+        assert!(upload.done);
+        assert_eq!(&sim.memory()[..CARDS.end], image.upload_region());
+        assert!(utils::verify_upload(&mut sim, &POLICY, image.upload_region()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn upload_diff_sends_changed_blocks_only() {
+        let mut sim = Simulator::demo();
+        let baseline = dump(&mut sim);
+        let mut image = baseline.clone();
+        image.set_card(5, &Card { rfid: "0102".into(), pin: String::new() }).unwrap();
+
+        let mut upload = Transfer::upload_diff(image.upload_region().to_vec(), Some(baseline.upload_region().to_vec()));
+        utils::mem_upload(&mut sim, &POLICY, &mut upload, &mut |_| true).unwrap();
+
+        assert_eq!(upload.progress.blocks_total, 1);
+        assert_eq!(&sim.memory()[0x50..0x60], &image.upload_region()[0x50..0x60]);
+    }
+
+    #[test]
+    fn enrolled_card_lands_in_the_first_free_slot() {
+        let mut sim = Simulator::demo();
+        sim.set_card(2, "", "").unwrap();
+        let baseline = dump(&mut sim);
+
+        let mut image = baseline.clone();
+        let slot = image.enroll(&Card { rfid: "04deadbeef".into(), pin: "9876".into() }).unwrap();
+        assert_eq!(slot, 2);
+
+        let mut upload = Transfer::upload_diff(image.upload_region().to_vec(), Some(baseline.upload_region().to_vec()));
+        utils::mem_upload(&mut sim, &POLICY, &mut upload, &mut |_| true).unwrap();
+
+        // UID from slot * 16, PIN digits from + 10, both 0xFF padded
+        let entry = &sim.memory()[slot * ENTRY_SIZE..(slot + 1) * ENTRY_SIZE];
+        assert_eq!(entry[..10], [0x04, 0xDE, 0xAD, 0xBE, 0xEF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(entry[10..], [9, 8, 7, 6, 0xFF, 0xFF]);
+        assert_eq!(upload.progress.blocks_total, 1);
+    }
+
+    #[test]
+    fn verify_reports_mismatched_blocks() {
+        let mut sim = Simulator::demo();
+        let image = dump(&mut sim);
+        sim.set_card(5, "0A0B", "").unwrap();
+        sim.memory[0x0001] = 0x02;
+
+        let mismatched = utils::verify_upload(&mut sim, &POLICY, image.upload_region()).unwrap();
+        assert_eq!(mismatched, [0x0000, 0x0050]);
+
+        utils::rewrite_blocks(&mut sim, &POLICY, image.upload_region(), &mismatched).unwrap();
+        assert!(utils::verify_upload(&mut sim, &POLICY, image.upload_region()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn dump_retries_flaky_blocks() {
+        let mut flaky = Flaky { sim: Simulator::demo(), exchanges: 0, fails: |n| n % 50 == 7 };
+        let mut transfer = Transfer::dump();
+        utils::mem_dump(&mut flaky, &POLICY, &mut transfer, &mut |_| true).unwrap();
+
+        assert_eq!(transfer.data, flaky.sim.memory());
+        assert!(!transfer.retried.is_empty());
+        assert!(transfer.retried.iter().all(|block| block.attempts == 2));
+    }
+
+    #[test]
+    fn dump_resumes_after_failure() {
+        let mut flaky = Flaky { sim: Simulator::demo(), exchanges: 0, fails: |n| n >= 100 };
+        let mut transfer = Transfer::dump();
+
+        let err = utils::mem_dump(&mut flaky, &POLICY, &mut transfer, &mut |_| true).unwrap_err();
+        assert!(matches!(err, AgrgError::Block { addr: 0x0C80, attempts: 3, .. }));
+        assert_eq!(transfer.next_addr, 100 * 32);
+        assert!(!transfer.done);
+
+        flaky.fails = |_| false;
+        utils::mem_dump(&mut flaky, &POLICY, &mut transfer, &mut |_| true).unwrap();
+        assert!(transfer.done);
+        assert_eq!(transfer.data, flaky.sim.memory());
+    }
+
+    #[test]
+    fn dump_can_be_cancelled() {
+        let mut sim = Simulator::demo();
+        let mut transfer = Transfer::dump();
+
+        let err = utils::mem_dump(&mut sim, &POLICY, &mut transfer, &mut |progress| progress.blocks_done < 10).unwrap_err();
+        assert!(err.is_cancelled());
+        assert_eq!(transfer.next_addr, 10 * 32);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut sim = Simulator::demo();
+        assert_eq!(utils::read_text(&mut sim).unwrap(), "Demo: office 101");
+
+        utils::write_text(&mut sim, "Door 2").unwrap();
+        assert_eq!(utils::read_text(&mut sim).unwrap(), "Door 2");
+    }
+
+    #[test]
+    fn clock_is_set() {
+        let mut sim = Simulator::demo();
+        utils::set_datetime(&mut sim, "12:34:56 01.02.30".into()).unwrap();
+
+        let raw: [u8; CLOCK_SIZE] = utils::get_datetime(&mut sim).unwrap().try_into().unwrap();
+        let time = bcd_to_datetime(&raw).unwrap();
+        assert_eq!(time.format("%d.%m.%Y %H:%M").to_string(), "01.02.2030 12:34");
+    }
+
+    #[test]
+    fn journal_wraps_to_the_start() {
+        let mut sim = Simulator::new("test");
+        let count = JOURNAL.len() / ENTRY_SIZE;
+        let start = sim.now();
+        for minute in 0..count + 2 {
+            sim.push_event_at(start + TimeDelta::minutes(minute as i64), 0x00, 0x00, [0xFF; 7]);
+        }
+
+        let ordered = crate::utils::journal::order(&sim.memory()[JOURNAL]);
+        assert_eq!(ordered.head, JOURNAL.start + 2 * ENTRY_SIZE);
+        assert!(ordered.wrapped);
+        assert_eq!(ordered.entries.len(), count);
+    }
+}
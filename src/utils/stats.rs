@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, io::Write};
+
+use chrono::{Local, NaiveDate, NaiveDateTime, Timelike};
+
+use super::{
+    error::Result,
+    journal::{JournalEntry, JournalEvent, TIME_FORMAT},
+    roster::Names,
+};
+
+/// Counters over a journal for the statistics tab and its report.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JournalStats {
+    pub total: usize,
+    pub first: Option<NaiveDateTime>,
+    pub last: Option<NaiveDateTime>,
+    pub per_day: BTreeMap<NaiveDate, usize>,
+    pub per_hour: [usize; 24],
+    /// Accesses by user slot.
+    pub per_slot: BTreeMap<u8, usize>,
+    pub power_on: usize,
+    pub forced_open: usize,
+    pub unknown_uid: usize,
+    pub unknown_pin: usize,
+    pub bad_admin_pin: usize,
+}
+
+impl JournalStats {
+    pub fn new(entries: &[JournalEntry]) -> Self {
+        let mut stats = JournalStats {
+            total: entries.len(),
+            first: entries.iter().map(|e| e.time).min(),
+            last: entries.iter().map(|e| e.time).max(),
+            ..JournalStats::default()
+        };
+
+        for entry in entries {
+            *stats.per_day.entry(entry.time.date()).or_default() += 1;
+            stats.per_hour[entry.time.hour() as usize] += 1;
+
+            match entry.event {
+                JournalEvent::UserAccess { slot } => *stats.per_slot.entry(slot).or_default() += 1,
+                JournalEvent::PowerOn => stats.power_on += 1,
+                JournalEvent::ForcedOpen => stats.forced_open += 1,
+                JournalEvent::UnknownUid { .. } => stats.unknown_uid += 1,
+                JournalEvent::UnknownPin { .. } => stats.unknown_pin += 1,
+                JournalEvent::BadAdminPin => stats.bad_admin_pin += 1,
+                _ => {},
+            }
+        }
+
+        stats
+    }
+
+    /// Wrong user and admin PINs together.
+    pub fn failed_pins(&self) -> usize {
+        self.unknown_pin + self.bad_admin_pin
+    }
+
+    /// Headline counters as label and value, in report order.
+    pub fn summary(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("Всего событий", self.total),
+            ("Проходов пользователей", self.per_slot.values().sum()),
+            ("Подач питания", self.power_on),
+            ("Принудительных освобождений/взломов", self.forced_open),
+            ("Незарегистрированных UID", self.unknown_uid),
+            ("Неверных PIN (пользователь и администратор)", self.failed_pins()),
+        ]
+    }
+}
+
+/// Writes the statistics as a plain text report, slots named through `names`.
+pub fn write_report(out: &mut impl Write, stats: &JournalStats, names: &Names) -> Result<()> {
+    writeln!(out, "Отчёт по журналу событий AGRG SH-D")?;
+    if let (Some(first), Some(last)) = (stats.first, stats.last) {
+        writeln!(out, "Период: {} - {}", first.format(TIME_FORMAT), last.format(TIME_FORMAT))?;
+    }
+    writeln!(out)?;
+
+    for (label, value) in stats.summary() {
+        writeln!(out, "{}: {}", label, value)?;
+    }
+
+    writeln!(out, "\nПроходы по пользователям")?;
+    for (slot, count) in &stats.per_slot {
+        writeln!(out, "{}: {}", names.slot(*slot as usize), count)?;
+    }
+
+    writeln!(out, "\nСобытия по дням")?;
+    for (day, count) in &stats.per_day {
+        writeln!(out, "{}: {}", day.format("%Y-%m-%d"), count)?;
+    }
+
+    writeln!(out, "\nСобытия по часам")?;
+    for (hour, count) in stats.per_hour.iter().enumerate().filter(|(_, &count)| count > 0) {
+        writeln!(out, "{:02}:00: {}", hour, count)?;
+    }
+
+    Ok(())
+}
+
+pub fn save_report(stats: &JournalStats, names: &Names) -> Result<()> {
+    let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
+
+    let file_path = rfd::FileDialog::new()
+        .set_title("Сохранение отчёта")
+        .set_file_name(format!("report_{}.txt", timestamp))
+        .save_file();
+
+    if let Some(path) = file_path {
+        let mut file = std::fs::File::create(path)?;
+        write_report(&mut file, stats, names)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logged(time: &str, event: JournalEvent) -> JournalEntry {
+        JournalEntry { time: NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap(), event, raw: None }
+    }
+
+    #[test]
+    fn counts_per_kind_and_day() {
+        let stats = JournalStats::new(&[
+            logged("2025-05-01 08:00", JournalEvent::PowerOn),
+            logged("2025-05-01 09:10", JournalEvent::UserAccess { slot: 1 }),
+            logged("2025-05-01 09:20", JournalEvent::UserAccess { slot: 2 }),
+            logged("2025-05-02 09:30", JournalEvent::UserAccess { slot: 1 }),
+            logged("2025-05-02 13:00", JournalEvent::UnknownUid { uid: "04a1".into() }),
+            logged("2025-05-02 13:01", JournalEvent::UnknownPin { pin: "0000".into() }),
+            logged("2025-05-04 22:00", JournalEvent::BadAdminPin),
+            logged("2025-05-04 23:00", JournalEvent::ForcedOpen),
+            logged("2025-05-04 23:05", JournalEvent::HandleLocked),
+        ]);
+
+        assert_eq!(stats.total, 9);
+        assert_eq!(stats.first.unwrap().to_string(), "2025-05-01 08:00:00");
+        assert_eq!(stats.last.unwrap().to_string(), "2025-05-04 23:05:00");
+        assert_eq!(stats.per_slot, BTreeMap::from([(1, 2), (2, 1)]));
+        assert_eq!(
+            (stats.power_on, stats.forced_open, stats.unknown_uid, stats.unknown_pin, stats.bad_admin_pin),
+            (1, 1, 1, 1, 1),
+        );
+        assert_eq!(stats.failed_pins(), 2);
+
+        let days: Vec<(String, usize)> = stats.per_day.iter().map(|(day, count)| (day.to_string(), *count)).collect();
+        assert_eq!(days, [("2025-05-01".into(), 3), ("2025-05-02".into(), 3), ("2025-05-04".into(), 3)]);
+        assert_eq!((stats.per_hour[9], stats.per_hour[13], stats.per_hour[23]), (3, 2, 2));
+        assert_eq!(stats.per_hour.iter().sum::<usize>(), 9);
+    }
+
+    #[test]
+    fn empty_journal() {
+        let stats = JournalStats::new(&[]);
+        assert_eq!(stats, JournalStats::default());
+        assert!(stats.summary().iter().all(|&(_, value)| value == 0));
+    }
+}
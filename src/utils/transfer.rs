@@ -0,0 +1,235 @@
+use std::time::Duration;
+
+use super::error::{AgrgError, Result};
+
+/// How often a single block is retried before the transfer gives up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    // wait before the first retry, doubled on every next one
+    pub backoff: Duration,
+}
+
+// overrides for the default policy, e.g. for a long or noisy cable
+pub const RETRIES_ENV: &str = "AGRG_RETRIES";
+pub const BACKOFF_ENV: &str = "AGRG_BACKOFF_MS";
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(50),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn no_retry() -> Self {
+        Self {
+            attempts: 1,
+            backoff: Duration::ZERO,
+        }
+    }
+
+    /// Default policy with `AGRG_RETRIES` and `AGRG_BACKOFF_MS` applied, bad values are ignored.
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+
+        if let Some(retries) = env_number(RETRIES_ENV) {
+            policy.set_retries(retries as u32);
+        }
+        if let Some(ms) = env_number(BACKOFF_ENV) {
+            policy.backoff = Duration::from_millis(ms);
+        }
+        policy
+    }
+
+    /// Retries after the first attempt.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.attempts = retries.saturating_add(1);
+    }
+
+    /// Runs `op` until it succeeds or attempts run out, returns the attempts used.
+    pub fn run<T>(&self, mut op: impl FnMut() -> Result<T>) -> (Result<T>, u32) {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+
+        loop {
+            match op() {
+                Ok(val) => return (Ok(val), attempt),
+                Err(e) if attempt >= self.attempts.max(1) => return (Err(e), attempt),
+                Err(e) => eprintln!("attempt {} failed: {}", attempt, e),
+            }
+
+            std::thread::sleep(backoff);
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+fn env_number(name: &str) -> Option<u64> {
+    let val = std::env::var(name).ok()?;
+    match val.trim().parse() {
+        Ok(num) => Some(num),
+        Err(_) => {
+            eprintln!("{}: not a number: {}", name, val);
+            None
+        },
+    }
+}
+
+/// Block that only went through after retrying.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetriedBlock {
+    pub addr: u16,
+    pub attempts: u32,
+}
+
+/// How far a transfer got, for progress bars.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub blocks_done: usize,
+    pub blocks_total: usize,
+    pub bytes_done: usize,
+}
+
+/// State of a dump or upload, kept between calls so a failed transfer
+/// resumes from the last good address instead of starting over.
+#[derive(Debug, Clone, Default)]
+pub struct Transfer {
+    /// Bytes read so far for a dump, the source image for an upload.
+    pub data: Vec<u8>,
+    /// First address not transferred yet.
+    pub next_addr: usize,
+    pub retried: Vec<RetriedBlock>,
+    pub done: bool,
+    /// Device contents an upload is diffed against, `None` writes every block.
+    pub baseline: Option<Vec<u8>>,
+    pub progress: Progress,
+}
+
+impl Transfer {
+    pub fn dump() -> Self {
+        Self::default()
+    }
+
+    pub fn upload(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            ..Self::default()
+        }
+    }
+
+    /// Upload that skips blocks already equal to `baseline`.
+    pub fn upload_diff(data: Vec<u8>, baseline: Option<Vec<u8>>) -> Self {
+        Self {
+            data,
+            baseline,
+            ..Self::default()
+        }
+    }
+
+    /// Runs one block with retries and advances past it on success.
+    pub fn step<T>(
+        &mut self,
+        policy: &RetryPolicy,
+        addr: usize,
+        len: usize,
+        op: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        let (res, attempts) = policy.run(op);
+
+        if attempts > 1 {
+            self.retried.push(RetriedBlock { addr: addr as u16, attempts });
+        }
+
+        match res {
+            Ok(val) => {
+                self.next_addr = addr + len;
+                self.progress.blocks_done += 1;
+                self.progress.bytes_done += len;
+                Ok(val)
+            },
+            Err(e) => Err(AgrgError::Block { addr, attempts, source: Box::new(e) }),
+        }
+    }
+
+    /// Hands the progress to `on_block`, fails with `Cancelled` if it returns false.
+    pub fn checkpoint(&self, on_block: &mut dyn FnMut(Progress) -> bool) -> Result<()> {
+        match on_block(self.progress) {
+            true => Ok(()),
+            false => Err(AgrgError::Cancelled),
+        }
+    }
+
+    /// One line per block that needed retries, for diagnosing flaky cables.
+    pub fn report(&self) -> String {
+        self.retried.iter()
+            .map(|b| format!("{:04X}: попыток {}\n", b.addr, b.attempts))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::transport::TransportError;
+
+    const POLICY: RetryPolicy = RetryPolicy { attempts: 3, backoff: Duration::ZERO };
+
+    fn timeout() -> AgrgError {
+        TransportError::Timeout { expected: 32 }.into()
+    }
+
+    #[test]
+    fn retries_until_success() {
+        let mut calls = 0;
+        let (res, attempts) = POLICY.run(|| {
+            calls += 1;
+            match calls {
+                1 | 2 => Err(timeout()),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!(res.unwrap(), 3);
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        let mut calls = 0;
+        let (res, attempts) = POLICY.run(|| -> Result<()> {
+            calls += 1;
+            Err(timeout())
+        });
+        assert!(res.is_err());
+        assert_eq!((attempts, calls), (3, 3));
+
+        let (res, attempts) = RetryPolicy::no_retry().run(|| -> Result<()> { Err(timeout()) });
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn step_advances_only_on_success() {
+        let mut transfer = Transfer::dump();
+
+        let mut failed_once = false;
+        transfer.step(&POLICY, 0x0000, 32, || match failed_once {
+            true => Ok(()),
+            false => {
+                failed_once = true;
+                Err(timeout())
+            },
+        }).unwrap();
+        assert_eq!(transfer.next_addr, 32);
+        assert_eq!(transfer.retried, [RetriedBlock { addr: 0x0000, attempts: 2 }]);
+        assert_eq!(transfer.progress, Progress { blocks_done: 1, blocks_total: 0, bytes_done: 32 });
+
+        let err = transfer.step(&POLICY, 0x0020, 32, || -> Result<()> { Err(timeout()) }).unwrap_err();
+        assert!(matches!(err, AgrgError::Block { addr: 0x0020, attempts: 3, .. }));
+        // resumes from the failed block
+        assert_eq!(transfer.next_addr, 32);
+    }
+}
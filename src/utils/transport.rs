@@ -0,0 +1,147 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+use serialport::{ClearBuffer, SerialPort};
+
+use super::error::Result;
+
+// handle baud rate, reference protocol documentation for details
+pub const BAUD_RATE: u32 = 38400;
+
+// long enough for the 255-byte info string on a slow USB-serial adapter
+pub const DEFAULT_DEADLINE: Duration = Duration::from_millis(500);
+
+// commands without a reply (writes, clock, text) go to EEPROM, the handle
+// needs this long before it takes the next frame
+pub const DEFAULT_SETTLE: Duration = Duration::from_millis(50);
+
+// single read() wait, the deadline bounds the whole frame
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Anything the SH-D protocol can be spoken over.
+///
+/// One transport is one open connection to one handle, so several of them
+/// can live side by side and tests can swap the real port for a fake one.
+pub trait Transport: Send {
+    /// Sends a single request frame.
+    fn send(&mut self, frame: &[u8]) -> Result<()>;
+
+    /// Receives exactly `expected` reply bytes or fails with a `TransportError`.
+    fn receive(&mut self, expected: usize) -> Result<Vec<u8>>;
+
+    /// Request/reply round trip.
+    fn exchange(&mut self, frame: &[u8], expected: usize) -> Result<Vec<u8>> {
+        self.send(frame)?;
+        self.receive(expected)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+    /// Nothing arrived before the deadline.
+    Timeout { expected: usize },
+    /// Part of the frame arrived before the deadline.
+    ShortFrame { expected: usize, got: usize },
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Timeout { expected } =>
+                write!(f, "timed out waiting for {} bytes", expected),
+            TransportError::ShortFrame { expected, got } =>
+                write!(f, "short frame: expected {} bytes, got {}", expected, got),
+        }
+    }
+}
+
+impl Error for TransportError {}
+
+impl TransportError {
+    pub fn check(expected: usize, got: usize) -> std::result::Result<(), TransportError> {
+        match got {
+            0 if expected > 0 => Err(TransportError::Timeout { expected }),
+            got if got < expected => Err(TransportError::ShortFrame { expected, got }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Serial port transport, keeps one port open for the whole session.
+pub struct SerialTransport {
+    name: String,
+    port: Box<dyn SerialPort>,
+    deadline: Duration,
+    settle: Duration,
+}
+
+impl SerialTransport {
+    pub fn open(name: &str) -> Result<Self> {
+        let builder = serialport::new(name, BAUD_RATE).timeout(POLL_INTERVAL);
+        let port = match builder.clone().open() {
+            Ok(port) => port,
+            // pseudo terminals (sh-d-bridge) have no modem lines to raise DTR on
+            Err(_) => builder.preserve_dtr_on_open().open()?,
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            port,
+            deadline: DEFAULT_DEADLINE,
+            settle: DEFAULT_SETTLE,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Overall time a single reply may take to arrive.
+    pub fn set_deadline(&mut self, deadline: Duration) {
+        self.deadline = deadline;
+    }
+
+    /// Pause after a command that gets no reply, before the next frame may be sent.
+    pub fn set_settle(&mut self, settle: Duration) {
+        self.settle = settle;
+    }
+}
+
+impl Transport for SerialTransport {
+    fn send(&mut self, frame: &[u8]) -> Result<()> {
+        // drop leftovers of a previous reply
+        self.port.clear(ClearBuffer::Input)?;
+
+        self.port.write_all(frame)?;
+        self.port.flush()?;
+
+        Ok(())
+    }
+
+    fn receive(&mut self, expected: usize) -> Result<Vec<u8>> {
+        // nothing comes back, but the next send would cut the write short
+        if expected == 0 {
+            std::thread::sleep(self.settle);
+            return Ok(Vec::new());
+        }
+
+        let deadline = Instant::now() + self.deadline;
+        let mut rx = vec![0; expected];
+        let mut got = 0;
+
+        while got < expected && Instant::now() < deadline {
+            match self.port.read(&mut rx[got..]) {
+                Ok(t) => got += t,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        TransportError::check(expected, got)?;
+        Ok(rx)
+    }
+}